@@ -0,0 +1,445 @@
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long output is relayed at most once the child exits.
+const DRAIN_LIMIT: Duration = Duration::from_secs(1);
+
+/// Set by SIGWINCH, to resize the pseudo-terminal after the terminal of ap.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn resized(_: libc::c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
+/// A running child process whose output is copied to the terminal and a log file.
+///
+/// If stdout of ap is a terminal, the child is attached to a pseudo-terminal relayed by ap, so
+/// that it still sees a terminal to colour its output and interact with. Otherwise its stdout and
+/// stderr are piped through. Stdin is passed to the child as is unless it is a terminal.
+///
+/// Once the child exits, output is relayed until it goes quiet rather than till EOF, which may
+/// never come if a process left in background by the child holds the output open, and for
+/// [`DRAIN_LIMIT`] at most if that process keeps writing.
+pub struct Capture {
+    child: Child,
+    relay: Relay,
+}
+
+enum Relay {
+    Pty {
+        master: File,
+        log: File,
+        /// Keeps the terminal of ap in raw mode, restored on drop
+        raw: Option<RawMode>,
+        /// Copies keys to the child if stdin is a terminal
+        input: Option<JoinHandle<()>>,
+        stop: Arc<AtomicBool>,
+    },
+    Pipe {
+        threads: Vec<JoinHandle<()>>,
+        /// Number of bytes relayed so far
+        relayed: Arc<AtomicU64>,
+    },
+}
+
+impl Capture {
+    pub fn spawn(cmd: &mut Command, log: File) -> io::Result<Capture> {
+        if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1 {
+            Capture::spawn_pty(cmd, log)
+        } else {
+            Capture::spawn_pipe(cmd, log)
+        }
+    }
+
+    fn spawn_pty(cmd: &mut Command, log: File) -> io::Result<Capture> {
+        let (master, slave) = open_pty()?;
+        let stdin_is_tty = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+
+        unsafe {
+            // same terminal settings and size as the terminal of ap
+            let mut termios: libc::termios = std::mem::zeroed();
+            if stdin_is_tty && libc::tcgetattr(libc::STDIN_FILENO, &mut termios) == 0 {
+                libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+            }
+        }
+        copy_window_size(libc::STDOUT_FILENO, slave.as_raw_fd());
+
+        // piped stdin is inherited, so that the child sees its end
+        if stdin_is_tty {
+            cmd.stdin(Stdio::from(slave.try_clone()?));
+        }
+        cmd.stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        unsafe {
+            // make the pseudo-terminal, i.e. stdout, the controlling terminal of the child
+            cmd.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(1, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = cmd.spawn()?;
+
+        // keys are passed to the child as is, e.g. ctrl-c interrupts the child through its
+        // terminal
+        let raw = if stdin_is_tty {
+            RawMode::enable(libc::STDIN_FILENO)
+        } else {
+            None
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let input = if stdin_is_tty {
+            let mut master = master.try_clone()?;
+            let stop = stop.clone();
+            Some(thread::spawn(move || forward_input(&mut master, &stop)))
+        } else {
+            None
+        };
+
+        RESIZED.store(false, Ordering::SeqCst);
+        unsafe {
+            libc::signal(libc::SIGWINCH, resized as *const () as libc::sighandler_t);
+        }
+
+        Ok(Capture {
+            child,
+            relay: Relay::Pty {
+                master,
+                log,
+                raw,
+                input,
+                stop,
+            },
+        })
+    }
+
+    fn spawn_pipe(cmd: &mut Command, log: File) -> io::Result<Capture> {
+        let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+        let log = Arc::new(Mutex::new(log));
+        let relayed = Arc::new(AtomicU64::new(0));
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let threads = vec![
+            tee(stdout, io::stdout(), log.clone(), relayed.clone()),
+            tee(stderr, io::stderr(), log, relayed.clone()),
+        ];
+
+        Ok(Capture {
+            child,
+            relay: Relay::Pipe { threads, relayed },
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Relay output until the child exits, and return its exit status.
+    pub fn wait(mut self) -> io::Result<ExitStatus> {
+        match self.relay {
+            Relay::Pty {
+                mut master,
+                mut log,
+                mut raw,
+                input,
+                stop,
+            } => {
+                let mut stdout = io::stdout();
+                let mut buffer = [0; 4096];
+                let mut status = None;
+                let mut exited = None;
+
+                loop {
+                    if RESIZED.swap(false, Ordering::SeqCst) {
+                        copy_window_size(libc::STDOUT_FILENO, master.as_raw_fd());
+                    }
+                    if status.is_none() {
+                        status = try_wait(self.child.id() as libc::pid_t, &mut raw)?;
+                        exited = status.map(|_| Instant::now());
+                    }
+                    if exited.is_some_and(|x| x.elapsed() > DRAIN_LIMIT) {
+                        break;
+                    }
+                    // drain what is left once the child exits
+                    let timeout = if status.is_some() { 50 } else { 100 };
+                    if !readable(master.as_raw_fd(), timeout) {
+                        if status.is_some() {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    match master.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            let _ = stdout.write_all(&buffer[..n]);
+                            let _ = stdout.flush();
+                            let _ = log.write_all(&buffer[..n]);
+                        }
+                        Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                        // EIO once the terminal is closed by all processes
+                        Err(_) => break,
+                    }
+                }
+
+                unsafe {
+                    libc::signal(libc::SIGWINCH, libc::SIG_DFL);
+                }
+                stop.store(true, Ordering::SeqCst);
+                if let Some(input) = input {
+                    let _ = input.join();
+                }
+                match status {
+                    Some(status) => Ok(status),
+                    None => self.child.wait(),
+                }
+            }
+            Relay::Pipe { threads, relayed } => {
+                let status = self.child.wait()?;
+
+                // drain until the output goes quiet, unfinished threads are left detached
+                let exited = Instant::now();
+                let mut last = relayed.load(Ordering::SeqCst);
+                let mut quiet = Instant::now();
+                while !threads.iter().all(|x| x.is_finished())
+                    && quiet.elapsed() < Duration::from_millis(100)
+                    && exited.elapsed() < DRAIN_LIMIT
+                {
+                    thread::sleep(Duration::from_millis(10));
+                    let now = relayed.load(Ordering::SeqCst);
+                    if now != last {
+                        last = now;
+                        quiet = Instant::now();
+                    }
+                }
+                for thread in threads {
+                    if thread.is_finished() {
+                        let _ = thread.join();
+                    }
+                }
+                Ok(status)
+            }
+        }
+    }
+}
+
+/// Check whether child `pid` exited without blocking.
+///
+/// If the child is stopped, e.g. by ctrl-z through its terminal, stop ap as well with its
+/// terminal restored, so that the shell takes over, then continue the child once ap is resumed.
+fn try_wait(pid: libc::pid_t, raw: &mut Option<RawMode>) -> io::Result<Option<ExitStatus>> {
+    let mut status = 0;
+    match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG | libc::WUNTRACED) } {
+        0 => return Ok(None),
+        x if x < 0 => {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::Interrupted => Ok(None),
+                _ => Err(error),
+            };
+        }
+        _ if !libc::WIFSTOPPED(status) => return Ok(Some(ExitStatus::from_raw(status))),
+        _ => {}
+    }
+
+    let fd = raw.take().map(|x| x.fd);
+    unsafe {
+        libc::raise(libc::SIGTSTP);
+    }
+
+    // resumed, e.g. by `fg`, the terminal may have been resized meanwhile
+    if let Some(fd) = fd {
+        *raw = RawMode::enable(fd);
+    }
+    RESIZED.store(true, Ordering::SeqCst);
+    unsafe {
+        libc::kill(-pid, libc::SIGCONT);
+    }
+    Ok(None)
+}
+
+fn open_pty() -> io::Result<(File, File)> {
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(fd);
+
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let name = libc::ptsname(fd);
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let name = CStr::from_ptr(name).to_string_lossy().to_string();
+
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(name)?;
+        Ok((master, slave))
+    }
+}
+
+fn copy_window_size(from: RawFd, to: RawFd) {
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(from, libc::TIOCGWINSZ, &mut size) == 0 {
+            libc::ioctl(to, libc::TIOCSWINSZ, &size);
+        }
+    }
+}
+
+fn readable(fd: RawFd, timeout: i32) -> bool {
+    let mut poll = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut poll, 1, timeout) > 0 }
+}
+
+/// Copy stdin of ap to the pseudo-terminal until `stop`.
+fn forward_input(master: &mut File, stop: &AtomicBool) {
+    let mut stdin = io::stdin();
+    let mut buffer = [0; 1024];
+
+    while !stop.load(Ordering::SeqCst) {
+        if !readable(libc::STDIN_FILENO, 100) {
+            continue;
+        }
+        match stdin.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                if master.write_all(&buffer[..n]).is_err() {
+                    break;
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+}
+
+fn tee<R, W>(
+    mut from: R,
+    mut to: W,
+    log: Arc<Mutex<File>>,
+    relayed: Arc<AtomicU64>,
+) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            match from.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let _ = to.write_all(&buffer[..n]);
+                    let _ = to.flush();
+                    if let Ok(mut log) = log.lock() {
+                        let _ = log.write_all(&buffer[..n]);
+                    }
+                    relayed.fetch_add(n as u64, Ordering::SeqCst);
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+    })
+}
+
+/// Terminal `fd` in raw mode, restored on drop.
+struct RawMode {
+    fd: RawFd,
+    saved: libc::termios,
+}
+
+impl RawMode {
+    fn enable(fd: RawFd) -> Option<RawMode> {
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut saved) != 0 {
+                return None;
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            Some(RawMode { fd, saved })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    fn log_file(name: &str) -> (PathBuf, File) {
+        let path = std::env::temp_dir().join(format!("ap-{}-{}.log", name, std::process::id()));
+        (path.clone(), File::create(path).unwrap())
+    }
+
+    #[test]
+    fn test_pipe() {
+        let (path, log) = log_file("pipe");
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo out; echo err >&2; exit 3"]);
+        let status = Capture::spawn_pipe(&mut cmd, log).unwrap().wait().unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let mut lines: Vec<_> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.sort();
+        assert_eq!(lines, vec!["err", "out"]);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_pipe_held_open() {
+        // the output is held open by a process left in background
+        let (path, log) = log_file("held");
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "sleep 5 & echo done"]);
+        let start = Instant::now();
+        let status = Capture::spawn_pipe(&mut cmd, log).unwrap().wait().unwrap();
+        assert!(status.success());
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "done\n");
+        let _ = fs::remove_file(path);
+    }
+}
@@ -1,6 +1,10 @@
 use std::path::{Path, PathBuf};
 
-use clap::{app_from_crate, App, AppSettings, Arg};
+use clap::{app_from_crate, App, AppSettings, Arg, ArgMatches};
+use toml::Value;
+
+use crate::env;
+use crate::settings::Settings;
 
 /// Return config dir.
 pub fn dir() -> PathBuf {
@@ -13,19 +17,164 @@ pub fn dir() -> PathBuf {
     }
 }
 
+/// Return state dir: `$XDG_STATE_HOME/ap`, defaults to `~/.local/state/ap`.
+pub fn state_dir() -> PathBuf {
+    match std::env::var("XDG_STATE_HOME") {
+        Ok(path) if !path.is_empty() => Path::new(&path).join("ap"),
+        _ => dirs::home_dir().unwrap().join(".local/state/ap"),
+    }
+}
+
+/// Point the state dir at a temporary directory of its own for the whole test run.
+#[cfg(test)]
+pub fn use_test_state_dir() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("ap-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::env::set_var("XDG_STATE_HOME", dir);
+    });
+}
+
 /// Global actions directory: `<confi_dir>/actions`
 pub fn global_actions_dir() -> PathBuf {
     dir().join("actions")
 }
 
+/// A directory of global actions, labelled by where it comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionDir {
+    pub label: String,
+    pub path: PathBuf,
+}
+
+/// All global action directories, in descending precedence:
+/// - `global`: [`global_actions_dir`]
+/// - `env`: entries of colon-separated `AP_ACTIONS_PATH`
+/// - `config`: `extra` directories from settings
+/// - `system`: `ap/actions` under each of `$XDG_CONFIG_DIRS` (defaults to `/etc/xdg`), then
+///   `/etc/ap/actions`
+///
+/// Entries of `AP_ACTIONS_PATH` and `extra` can be given a label of their own as
+/// `{label}={path}`.
+pub fn action_dirs(extra: &[String]) -> Vec<ActionDir> {
+    let mut dirs = vec![ActionDir {
+        label: "global".to_string(),
+        path: global_actions_dir(),
+    }];
+
+    if let Ok(paths) = std::env::var("AP_ACTIONS_PATH") {
+        dirs.extend(paths.split(':').filter_map(|x| action_dir(x, "env")));
+    }
+
+    dirs.extend(extra.iter().filter_map(|x| action_dir(x, "config")));
+
+    let system = match std::env::var("XDG_CONFIG_DIRS") {
+        Ok(paths) if !paths.is_empty() => paths,
+        _ => "/etc/xdg".to_string(),
+    };
+    dirs.extend(
+        system
+            .split(':')
+            .filter(|x| !x.is_empty())
+            .map(|x| Path::new(x).join("ap/actions"))
+            .chain(std::iter::once(PathBuf::from("/etc/ap/actions")))
+            .map(|path| ActionDir {
+                label: "system".to_string(),
+                path,
+            }),
+    );
+
+    let mut seen = Vec::new();
+    dirs.retain(|x| {
+        let new = !seen.contains(&x.path);
+        seen.push(x.path.clone());
+        new
+    });
+    dirs
+}
+
+/// Parse an action directory entry `{label}={path}` or `{path}`.
+fn action_dir(entry: &str, default_label: &str) -> Option<ActionDir> {
+    let entry = entry.trim();
+    if entry.is_empty() {
+        return None;
+    }
+
+    let (label, path) = match entry.split_once('=') {
+        Some((label, path)) if !label.contains('/') => (label.trim(), path.trim()),
+        _ => (default_label, entry),
+    };
+    Some(ActionDir {
+        label: label.to_string(),
+        path: PathBuf::from(env::expand_path(path)),
+    })
+}
+
+/// Output format of `ap list`.
+pub enum ListFormat {
+    Table,
+    Json,
+    Tsv,
+}
+
+/// Filter of `ap history` by exit status.
+pub enum StatusFilter {
+    Succeeded,
+    Failed,
+}
+
 pub enum Task {
-    New { name: String, is_global: bool },
-    Execute { only_tmux_action: bool },
+    New {
+        name: String,
+        is_global: bool,
+    },
+    Execute {
+        only_tmux_action: bool,
+        dry_run: bool,
+    },
+    Run {
+        name: String,
+        args: Vec<String>,
+        background: bool,
+        dry_run: bool,
+    },
+    List {
+        format: ListFormat,
+        /// Only list actions in this group
+        group: Option<String>,
+    },
+    ShowConfig,
+    History {
+        project: Option<PathBuf>,
+        status: Option<StatusFilter>,
+        rerun: Option<usize>,
+    },
+    Again,
+    Doctor,
+    Jobs,
+    Logs {
+        /// ID of a background job, or name of an action
+        target: String,
+        /// Keep printing new output until the job ends
+        follow: bool,
+        /// Number of most recent runs of the action to print
+        last: usize,
+    },
+    Attach {
+        job: u32,
+    },
+    Stop {
+        job: u32,
+    },
+    /// Supervise background job, see `jobs::supervise`
+    Supervise(u32),
     Preview(String),
 }
 
 pub struct Config {
     pub task: Task,
+    pub settings: Settings,
 }
 
 impl Config {
@@ -46,6 +195,148 @@ impl Config {
                     .index(1),
             );
 
+        let dry_run = || {
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Print what the chosen action would execute, without running anything")
+        };
+
+        let run = App::new("run")
+            .visible_alias("r")
+            .about("Run an action by name, without the picker")
+            .arg(
+                Arg::new("ACTION_NAME")
+                    .help("The file stem or title of the action")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::new("ARGS")
+                    .help("Arguments passed to the action, after `--`")
+                    .index(2)
+                    .multiple_values(true)
+                    .last(true),
+            )
+            .arg(
+                Arg::new("background")
+                    .long("bg")
+                    .help("Run as a background job, see `ap jobs`"),
+            )
+            .arg(dry_run());
+
+        let list = App::new("list")
+            .visible_alias("l")
+            .about("List all discovered actions")
+            .arg(
+                Arg::new("json")
+                    .long("json")
+                    .conflicts_with("tsv")
+                    .help("Print as JSON"),
+            )
+            .arg(
+                Arg::new("tsv")
+                    .long("tsv")
+                    .help("Print as tab-separated values"),
+            )
+            .arg(
+                Arg::new("group")
+                    .long("group")
+                    .short('g')
+                    .takes_value(true)
+                    .value_name("GROUP")
+                    .help("Only list actions in GROUP, e.g. `db` for `.ap-actions/db/*`"),
+            );
+
+        let history = App::new("history")
+            .visible_alias("h")
+            .about("List past runs of actions")
+            .arg(
+                Arg::new("project")
+                    .short('p')
+                    .long("project")
+                    .takes_value(true)
+                    .min_values(0)
+                    .value_name("DIR")
+                    .help("Only runs invoked inside DIR, defaults to current project"),
+            )
+            .arg(
+                Arg::new("status")
+                    .long("status")
+                    .takes_value(true)
+                    .possible_values(["ok", "failed"])
+                    .help("Only runs with the given exit status"),
+            )
+            .arg(
+                Arg::new("rerun")
+                    .long("rerun")
+                    .takes_value(true)
+                    .value_name("ID")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("Re-run the run ID with its original arguments and working directory"),
+            );
+
+        let again = App::new("again")
+            .about("Re-run the last run in current project with its original arguments");
+
+        let job_arg = || {
+            Arg::new("JOB")
+                .help("ID of the job, see `ap jobs`")
+                .required(true)
+                .index(1)
+                .value_parser(clap::value_parser!(u32))
+        };
+
+        let jobs = App::new("jobs").about("List background jobs started by `ap run --bg`");
+
+        let logs = App::new("logs")
+            .about("Print output of a background job, or of recent runs of an action")
+            .arg(
+                Arg::new("TARGET")
+                    .help(
+                        "ID of a background job, see `ap jobs`, or name of an action run in \
+                         current project, a job ID takes precedence",
+                    )
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::new("follow")
+                    .short('f')
+                    .long("follow")
+                    .help("Keep printing new output until the job ends, for jobs only"),
+            )
+            .arg(
+                Arg::new("last")
+                    .long("last")
+                    .value_name("N")
+                    .default_value("1")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("Print logs of the last N runs of the action"),
+            );
+
+        let attach = App::new("attach")
+            .about("Follow output of a background job until it ends, same as `logs --follow`")
+            .arg(job_arg());
+
+        let stop = App::new("stop")
+            .about("Terminate the process group of a background job")
+            .arg(job_arg());
+
+        let supervise = App::new("supervise")
+            .about("Supervise a background job")
+            .arg(job_arg())
+            .setting(AppSettings::Hidden);
+
+        let doctor = App::new("doctor")
+            .about("Diagnose action files which fail to load and missing external tools");
+
+        let config = App::new("config")
+            .about("Inspect configuration")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                App::new("show").about("Print effective configuration and source of each value"),
+            );
+
         let preview = App::new("preview")
             .visible_alias("p")
             .about("Generate fzf preview content for ACTION_PATH")
@@ -59,10 +350,46 @@ impl Config {
 
         let matches = app_from_crate!()
             .subcommand(new)
+            .subcommand(run)
+            .subcommand(list)
+            .subcommand(history)
+            .subcommand(again)
+            .subcommand(jobs)
+            .subcommand(logs)
+            .subcommand(attach)
+            .subcommand(stop)
+            .subcommand(supervise)
+            .subcommand(doctor)
+            .subcommand(config)
             .subcommand(preview)
             .arg(Arg::new("tmux").short('t').help("Only show tmux actions"))
+            .arg(dry_run())
+            .arg(
+                Arg::new("height")
+                    .long("height")
+                    .takes_value(true)
+                    .global(true)
+                    .help("Height of the picker, overrides `picker.height`"),
+            )
+            .arg(
+                Arg::new("sort")
+                    .long("sort")
+                    .takes_value(true)
+                    .possible_values(["discovery", "frecency", "alpha"])
+                    .global(true)
+                    .help("Order of actions in the picker, overrides `picker.sort`"),
+            )
+            .arg(
+                Arg::new("editor")
+                    .long("editor")
+                    .takes_value(true)
+                    .global(true)
+                    .help("Editor command, overrides `editor`"),
+            )
             .get_matches();
 
+        let settings = Settings::load(overrides(&matches));
+
         let task = if let Some(matches) = matches.subcommand_matches("preview") {
             let path = matches.value_of("ACTION_PATH").unwrap().to_string();
             Task::Preview(path)
@@ -73,13 +400,125 @@ impl Config {
                 name,
                 is_global: global,
             }
+        } else if let Some(matches) = matches.subcommand_matches("run") {
+            let name = matches.value_of("ACTION_NAME").unwrap().to_string();
+            let args = matches
+                .values_of("ARGS")
+                .map(|x| x.map(String::from).collect())
+                .unwrap_or_default();
+            let background = matches.is_present("background");
+            let dry_run = matches.is_present("dry-run");
+            Task::Run {
+                name,
+                args,
+                background,
+                dry_run,
+            }
+        } else if let Some(matches) = matches.subcommand_matches("list") {
+            let format = if matches.is_present("json") {
+                ListFormat::Json
+            } else if matches.is_present("tsv") {
+                ListFormat::Tsv
+            } else {
+                ListFormat::Table
+            };
+            let group = matches.value_of("group").map(String::from);
+            Task::List { format, group }
+        } else if let Some(matches) = matches.subcommand_matches("history") {
+            let project = if matches.is_present("project") {
+                let dir = matches.value_of("project").map(PathBuf::from);
+                Some(dir.unwrap_or_else(crate::history::current_project))
+            } else {
+                None
+            };
+            let status = match matches.value_of("status") {
+                Some("ok") => Some(StatusFilter::Succeeded),
+                Some(_) => Some(StatusFilter::Failed),
+                None => None,
+            };
+            let rerun = matches.get_one::<usize>("rerun").copied();
+            Task::History {
+                project,
+                status,
+                rerun,
+            }
+        } else if matches.subcommand_matches("again").is_some() {
+            Task::Again
+        } else if matches.subcommand_matches("jobs").is_some() {
+            Task::Jobs
+        } else if let Some(matches) = matches.subcommand_matches("logs") {
+            let target = matches.value_of("TARGET").unwrap().to_string();
+            let follow = matches.is_present("follow");
+            let last = *matches.get_one::<usize>("last").unwrap();
+            Task::Logs {
+                target,
+                follow,
+                last,
+            }
+        } else if let Some(matches) = matches.subcommand_matches("attach") {
+            let job = *matches.get_one::<u32>("JOB").unwrap();
+            Task::Attach { job }
+        } else if let Some(matches) = matches.subcommand_matches("stop") {
+            let job = *matches.get_one::<u32>("JOB").unwrap();
+            Task::Stop { job }
+        } else if let Some(matches) = matches.subcommand_matches("supervise") {
+            let job = *matches.get_one::<u32>("JOB").unwrap();
+            Task::Supervise(job)
+        } else if matches.subcommand_matches("doctor").is_some() {
+            Task::Doctor
+        } else if matches.subcommand_matches("config").is_some() {
+            Task::ShowConfig
         } else {
             let flag = matches.is_present("tmux");
+            let dry_run = matches.is_present("dry-run");
             Task::Execute {
                 only_tmux_action: flag,
+                dry_run,
             }
         };
 
-        Config { task }
+        Config { task, settings }
+    }
+}
+
+/// Settings overridden by command line flags.
+fn overrides(matches: &ArgMatches) -> Vec<(&'static str, Value)> {
+    // global args are propagated down to the innermost subcommand
+    let mut matches = matches;
+    while let Some((_, sub)) = matches.subcommand() {
+        matches = sub;
+    }
+
+    let mut overrides = Vec::new();
+    if let Some(height) = matches.value_of("height") {
+        overrides.push(("picker.height", Value::String(height.to_string())));
+    }
+    if let Some(sort) = matches.value_of("sort") {
+        overrides.push(("picker.sort", Value::String(sort.to_string())));
+    }
+    if let Some(editor) = matches.value_of("editor") {
+        overrides.push(("editor", Value::String(editor.to_string())));
+    }
+    overrides
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    #[test]
+    fn test_action_dir() {
+        let dir = action_dir("team = /opt/team/actions", "config").unwrap();
+        assert_eq!(dir.label, "team");
+        assert_eq!(dir.path, PathBuf::from("/opt/team/actions"));
+
+        let dir = action_dir("/opt/a=b/actions", "env").unwrap();
+        assert_eq!(dir.label, "env");
+        assert_eq!(dir.path, PathBuf::from("/opt/a=b/actions"));
+
+        assert_eq!(action_dir(" ", "env"), None);
     }
 }
@@ -1,66 +1,329 @@
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::logging::*;
 
 use crate::config;
-use crate::model::Action;
+use crate::model::{Action, ActionLoadError, INLINE_FILE, PROJECT_INLINE_FILE};
+use crate::provider;
+use crate::settings::DiscoverySettings;
 
-/// Lookup actions from `.ap-actions` directory under the argument `path`.
-fn actions_from<P: AsRef<Path>>(path: P) -> Vec<Action> {
-    let entries = fs::read_dir(&path);
+/// Lookup actions from `.ap-actions` directory under the argument `path`, including those in its
+/// subdirectories, which form groups named after their relative paths, e.g. `db/migrate` for
+/// `.ap-actions/db/migrate`.
+fn actions_from<P: AsRef<Path>>(path: P, errors: &mut Vec<ActionLoadError>) -> Vec<Action> {
+    group_actions(path.as_ref(), None, errors)
+}
+
+fn group_actions(
+    path: &Path,
+    group: Option<&str>,
+    errors: &mut Vec<ActionLoadError>,
+) -> Vec<Action> {
+    let entries = fs::read_dir(path);
     if let Err(error) = entries {
         info!(
             "failed at `read_dir`:\n  path: {:?}\n  error: {:#?}",
-            path.as_ref(),
-            error,
+            path, error,
         );
         return Vec::new();
     }
 
-    entries
-        .unwrap() // checked above
-        .filter_map(|entry| {
-            if entry.is_err() {
-                error!("failed to unwrap entry: {:?}", &entry);
-                return None;
+    let mut files = Vec::new();
+    let mut subgroups = Vec::new();
+
+    for entry in entries.unwrap() {
+        // checked above
+        if entry.is_err() {
+            error!("failed to unwrap entry: {:?}", &entry);
+            continue;
+        }
+
+        let entry = entry.unwrap();
+        if entry.file_type().map(|x| x.is_dir()).unwrap_or(false) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                info!("skip hidden directory: {:?}", &entry.path());
+                continue;
             }
+            let subgroup = match group {
+                Some(group) => format!("{}/{}", group, name),
+                None => name,
+            };
+            subgroups.push((entry.path(), subgroup));
+        } else {
+            files.push(entry.path());
+        }
+    }
 
-            let entry = entry.unwrap();
-            if entry.path().extension() == Some(OsStr::new("toml")) {
-                info!("skip toml file: {:?}", &entry.path());
-                return None;
+    // the order decides which of duplicates comes first, keep it stable
+    files.sort();
+
+    let mut actions = Vec::new();
+    for file in &files {
+        if file.extension() != Some(OsStr::new("toml")) {
+            collect(Action::load(file), &mut actions, errors);
+            continue;
+        }
+
+        let name = file.file_name().unwrap_or_default();
+        let orphan = name != INLINE_FILE
+            && name != PROJECT_INLINE_FILE
+            && !files
+                .iter()
+                .any(|x| x != file && &x.with_extension("toml") == file);
+        if orphan {
+            let error = ActionLoadError::OrphanMetadata(file.clone());
+            warn!("{:?}: {}", file, error);
+            errors.push(error);
+        } else {
+            info!("skip toml file: {:?}", file);
+        }
+    }
+
+    actions.extend(inline_actions(&path.join(INLINE_FILE), path, errors));
+    for action in &mut actions {
+        action.group = group.map(String::from);
+    }
+
+    subgroups.sort();
+    for (dir, subgroup) in subgroups {
+        actions.extend(group_actions(&dir, Some(&subgroup), errors));
+    }
+    actions
+}
+
+/// Push the loaded action to `actions`, or the error to `errors`.
+fn collect(
+    result: Result<Action, ActionLoadError>,
+    actions: &mut Vec<Action>,
+    errors: &mut Vec<ActionLoadError>,
+) {
+    match result {
+        Ok(action) => actions.push(action),
+        Err(error) => {
+            error.log();
+            errors.push(error);
+        }
+    }
+}
+
+/// Lookup inline actions defined in toml file `path` for `.ap-actions` directory `dir`.
+fn inline_actions(path: &Path, dir: &Path, errors: &mut Vec<ActionLoadError>) -> Vec<Action> {
+    let mut actions = Vec::new();
+    for entry in Action::load_inline_entries(path, dir) {
+        collect(entry, &mut actions, errors);
+    }
+
+    actions.retain(|action| {
+        // script file of the same name takes precedence
+        let shadowed = action.path.exists();
+        if shadowed {
+            warn!("skip inline action shadowed by file: {:?}", action.path);
+        }
+        !shadowed
+    });
+    actions
+}
+
+fn global_actions(settings: &DiscoverySettings, errors: &mut Vec<ActionLoadError>) -> Vec<Action> {
+    config::action_dirs(&settings.action_dirs)
+        .into_iter()
+        .flat_map(|dir| {
+            let mut actions = actions_from(&dir.path, errors);
+            for action in &mut actions {
+                action.label = Some(dir.label.clone());
             }
+            actions
+        })
+        .collect()
+}
 
-            Action::load_from(entry.path())
+fn local_actions<P: AsRef<Path>>(
+    path: &P,
+    settings: &DiscoverySettings,
+    errors: &mut Vec<ActionLoadError>,
+) -> Vec<Action> {
+    search_dirs(path.as_ref(), settings)
+        .into_iter()
+        .flat_map(|path| {
+            let dir = path.join(".ap-actions");
+            let mut actions = actions_from(&dir, errors);
+            actions.extend(inline_actions(
+                &path.join(PROJECT_INLINE_FILE),
+                &dir,
+                errors,
+            ));
+            actions
         })
         .collect()
 }
 
-fn global_actions() -> Vec<Action> {
-    let path = config::global_actions_dir();
-    actions_from(&path)
+/// Directories to look for local actions in, from `path` up to the first boundary (inclusive):
+/// a git root, the home directory, a directory containing the root marker file or declaring
+/// `inherit = false`, as configured by `settings`.
+fn search_dirs<'a>(path: &'a Path, settings: &DiscoverySettings) -> Vec<&'a Path> {
+    let home = dirs::home_dir();
+    let mut dirs = Vec::new();
+
+    for dir in path.ancestors() {
+        if settings.max_depth > 0 && dirs.len() >= settings.max_depth as usize {
+            debug!("stop discovery at max depth {}", settings.max_depth);
+            break;
+        }
+        dirs.push(dir);
+
+        let boundary = (settings.stop_at_git_root && dir.join(".git").exists())
+            || (settings.stop_at_home && home.as_deref() == Some(dir))
+            || (!settings.root_marker.is_empty() && dir.join(&settings.root_marker).exists())
+            || !inherits(dir);
+        if boundary {
+            debug!("stop discovery at boundary {:?}", dir);
+            break;
+        }
+    }
+
+    dirs
 }
 
-fn local_actions<P: AsRef<Path>>(path: &P) -> Vec<Action> {
-    path.as_ref()
-        .ancestors()
-        .map(|path| actions_from(path.join(".ap-actions")))
-        .flatten()
-        .collect()
+/// Whether actions of ancestors are inherited by project directory `dir`, `false` if
+/// `.ap-actions/.ap.toml` or `.ap.toml` under it sets `inherit = false`.
+fn inherits(dir: &Path) -> bool {
+    [
+        dir.join(".ap-actions").join(PROJECT_INLINE_FILE),
+        dir.join(PROJECT_INLINE_FILE),
+    ]
+    .iter()
+    .filter_map(|path| fs::read_to_string(path).ok())
+    .filter_map(|text| toml::from_str::<toml::Value>(&text).ok())
+    .all(|file| file.get("inherit").and_then(|x| x.as_bool()) != Some(false))
+}
+
+/// Result of discovery: the actions found, and the candidate files failed to load.
+pub struct Discovery {
+    pub actions: Vec<Action>,
+    pub errors: Vec<ActionLoadError>,
+    /// Paths of actions dropped as duplicates, each with the path of the one kept
+    pub duplicates: Vec<(PathBuf, PathBuf)>,
 }
 
-pub fn actions() -> Vec<Action> {
+/// Discover actions of current directory: local ones, those synthesized from task runners, then
+/// global ones.
+pub fn scan(settings: &DiscoverySettings) -> Discovery {
     let mut actions = Vec::new();
+    let mut errors = Vec::new();
 
     if let Ok(path) = std::env::current_dir() {
-        actions.extend(local_actions(&path));
+        actions.extend(local_actions(&path, settings, &mut errors));
+        actions.extend(provider::actions(&path));
     }
 
-    actions.extend(global_actions());
+    actions.extend(global_actions(settings, &mut errors));
 
+    let mut duplicates = Vec::new();
+    Discovery {
+        actions: dedup(actions, settings.show_shadowed, &mut duplicates),
+        errors,
+        duplicates,
+    }
+}
+
+pub fn actions(settings: &DiscoverySettings) -> Vec<Action> {
+    scan(settings).actions
+}
+
+/// Drop duplicates of the same file (e.g. symlinks to another action), and mark actions shadowed
+/// by ones of the same name from a closer scope, which come first in `actions`.
+///
+/// Of duplicates, the file itself is kept, or the first symlink if the file is not among
+/// `actions`. Names of the dropped ones become its `aliases`, and their paths are pushed to
+/// `duplicates` along with its path.
+///
+/// Shadowed actions are dropped unless `show_shadowed`.
+fn dedup(
+    actions: Vec<Action>,
+    show_shadowed: bool,
+    duplicates: &mut Vec<(PathBuf, PathBuf)>,
+) -> Vec<Action> {
+    // virtual paths of inline actions do not exist and are kept as is
+    let files: Vec<_> = actions
+        .iter()
+        .map(|x| fs::canonicalize(&x.path).unwrap_or_else(|_| x.path.clone()))
+        .collect();
+
+    let mut survivors: HashMap<&PathBuf, usize> = HashMap::new();
+    for (i, action) in actions.iter().enumerate() {
+        match survivors.get(&files[i]) {
+            Some(&kept) if action.path.is_symlink() || !actions[kept].path.is_symlink() => {}
+            _ => {
+                survivors.insert(&files[i], i);
+            }
+        }
+    }
+
+    let mut aliases: HashMap<usize, Vec<String>> = HashMap::new();
+    for (i, action) in actions.iter().enumerate() {
+        let kept = survivors[&files[i]];
+        if kept != i {
+            info!("skip duplicate action: {:?}", action.path);
+            aliases.entry(kept).or_default().push(action.name());
+            duplicates.push((action.path.clone(), actions[kept].path.clone()));
+        }
+    }
+
+    let mut scopes: HashMap<String, PathBuf> = HashMap::new();
     actions
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, mut action)| {
+            if survivors[&files[i]] != i {
+                return None;
+            }
+            action.aliases = aliases.remove(&i).unwrap_or_default();
+
+            let scope = action.actions_dir().to_path_buf();
+            match scopes.get(&action.name()) {
+                Some(closer) if closer != &scope => {
+                    info!(
+                        "action {:?} is shadowed by one in {:?}",
+                        action.path, closer
+                    );
+                    action.shadowed = true;
+                }
+                Some(_) => {}
+                None => {
+                    scopes.insert(action.name(), scope);
+                }
+            }
+
+            (show_shadowed || !action.shadowed).then_some(action)
+        })
+        .collect()
+}
+
+/// Return the nearest ancestor of `path` (inclusive) that contains a `.git` entry.
+pub fn git_root<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    path.as_ref()
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Find actions matching `name`, by file stem (or that of a symlink to it) first, then by title.
+/// Shadowed actions are ignored.
+pub fn lookup<'a>(actions: &'a [Action], name: &str) -> Vec<&'a Action> {
+    let actions = || actions.iter().filter(|x| !x.shadowed);
+
+    let by_name: Vec<_> = actions()
+        .filter(|x| x.name() == name || x.aliases.iter().any(|x| x == name))
+        .collect();
+    if !by_name.is_empty() {
+        return by_name;
+    }
+
+    actions().filter(|x| x.title == name).collect()
 }
 
 #[cfg(test)]
@@ -72,8 +335,11 @@ mod tests {
 
     #[test]
     fn test_local_actions() {
-        let path = Path::new("/Users/mudox/Develop/Rust/ap/tests/dir/d0/d1/d2/d3");
-        let actions = local_actions(&path);
+        let path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/dir/d0/d1/d2/d3"
+        ));
+        let actions = local_actions(&path, &DiscoverySettings::default(), &mut Vec::new());
         println!("actions: {:#?}", &actions);
 
         let ac1 = Action {
@@ -86,8 +352,7 @@ mod tests {
             icon: Some("ac1".to_string()),
             title: "Title of ac1".to_string(),
             description: Some("Description of ac1\n".to_string()),
-            tmux: None,
-            cd: None,
+            ..Default::default()
         };
 
         let ac3 = Action {
@@ -95,11 +360,131 @@ mod tests {
             icon: Some("ac3".to_string()),
             title: "Title of ac3".to_string(),
             description: Some("Description of ac3\n".to_string()),
-            tmux: None,
-            cd: None,
+            ..Default::default()
         };
 
         assert_eq!(actions[0], ac3);
         assert_eq!(actions[1], ac1);
     }
+
+    #[test]
+    fn test_search_dirs() {
+        let path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/dir/d0/d1/d2/d3"
+        ));
+
+        let settings = DiscoverySettings {
+            max_depth: 2,
+            ..Default::default()
+        };
+        let dirs = search_dirs(path, &settings);
+        assert_eq!(dirs, vec![path, path.parent().unwrap()]);
+
+        let settings = DiscoverySettings {
+            root_marker: "d3".to_string(),
+            ..Default::default()
+        };
+        let dirs = search_dirs(path, &settings);
+        assert_eq!(dirs.last(), Some(&path.parent().unwrap()));
+    }
+
+    #[test]
+    fn test_group_actions() {
+        let dir = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/dir/.ap-actions"
+        ));
+        let actions = actions_from(dir, &mut Vec::new());
+
+        let migrate = actions.iter().find(|x| x.name() == "db/migrate").unwrap();
+        assert_eq!(migrate.group.as_deref(), Some("db"));
+        assert_eq!(migrate.actions_dir(), dir);
+        assert_eq!(migrate.project_dir(), dir.parent().unwrap());
+    }
+
+    #[test]
+    fn test_dedup() {
+        let dir = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dir"));
+        let action = |path: PathBuf| Action {
+            path,
+            ..Default::default()
+        };
+        let actions = || {
+            vec![
+                action(dir.join("d0/d1/.ap-actions/ac1")),
+                action(dir.join(".ap-actions/can-run")),
+                action(dir.join(".ap-actions/link-to-can-run")),
+                action(dir.join(".ap-actions/ac1")),
+            ]
+        };
+
+        let paths = |actions: Vec<Action>| -> Vec<_> {
+            actions.into_iter().map(|x| (x.path, x.shadowed)).collect()
+        };
+
+        assert_eq!(
+            paths(dedup(actions(), false, &mut Vec::new())),
+            vec![
+                (dir.join("d0/d1/.ap-actions/ac1"), false),
+                (dir.join(".ap-actions/can-run"), false),
+            ]
+        );
+        assert_eq!(
+            paths(dedup(actions(), true, &mut Vec::new())),
+            vec![
+                (dir.join("d0/d1/.ap-actions/ac1"), false),
+                (dir.join(".ap-actions/can-run"), false),
+                (dir.join(".ap-actions/ac1"), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dedup_symlink() {
+        let dir = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/dir/.ap-actions"
+        ));
+        let action = |name: &str| Action {
+            path: dir.join(name),
+            ..Default::default()
+        };
+        let actions = vec![action("link-to-can-run"), action("can-run")];
+
+        let mut duplicates = Vec::new();
+        let actions = dedup(actions, false, &mut duplicates);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].path, dir.join("can-run"));
+        assert_eq!(actions[0].aliases, vec!["link-to-can-run".to_string()]);
+        assert_eq!(
+            duplicates,
+            vec![(dir.join("link-to-can-run"), dir.join("can-run"))]
+        );
+
+        assert_eq!(lookup(&actions, "link-to-can-run"), vec![&actions[0]]);
+        assert_eq!(lookup(&actions, "can-run"), vec![&actions[0]]);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let action = |path: &str, title: &str| Action {
+            path: PathBuf::from(path),
+            title: title.to_string(),
+            ..Default::default()
+        };
+        let actions = vec![
+            action("/a/.ap-actions/build.zsh", "Build"),
+            action("/a/.ap-actions/test", "build"),
+            action("/.ap-actions/build", "Build all"),
+        ];
+
+        let found = lookup(&actions, "build");
+        assert_eq!(found, vec![&actions[0], &actions[2]]);
+
+        let found = lookup(&actions, "Build all");
+        assert_eq!(found, vec![&actions[2]]);
+
+        assert!(lookup(&actions, "deploy").is_empty());
+    }
 }
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use console::{pad_str, style, Alignment};
+use is_executable::is_executable;
+
+use crate::discover;
+use crate::settings::{DiscoverySettings, Settings};
+
+/// External tools ap relies on, and what for.
+const TOOLS: [(&str, &str); 4] = [
+    ("fzf", "the action picker"),
+    ("bat", "highlighting in preview"),
+    ("file", "file type in preview"),
+    ("tmux", "actions with `tmux` settings"),
+];
+
+/// Status of a candidate action file or a tool.
+enum Status {
+    Ok(String),
+    /// Fine but not in use, e.g. a shadowed action
+    Unused(String),
+    Broken(String),
+}
+
+/// Print status of every candidate action file and external tool, return 1 if any problem is
+/// found.
+pub fn doctor(settings: &Settings) -> i32 {
+    let discovery = discover::scan(&DiscoverySettings {
+        show_shadowed: true,
+        ..settings.discovery.clone()
+    });
+    let actions = &discovery.actions;
+
+    // actions synthesized from task runners are not files of ap
+    let mut entries: Vec<(PathBuf, Status)> = actions
+        .iter()
+        .filter(|x| x.provider.is_none())
+        .map(|x| {
+            let mut status = match x.source {
+                Some(ref source) => format!("{} (inline in {})", x.title, source.display()),
+                None => x.title.clone(),
+            };
+            if !x.shadowed {
+                return (x.path.clone(), Status::Ok(status));
+            }
+
+            let closer = actions.iter().find(|y| !y.shadowed && y.name() == x.name());
+            if let Some(closer) = closer {
+                status.push_str(&format!(", shadowed by {}", closer.path.display()));
+            }
+            (x.path.clone(), Status::Unused(status))
+        })
+        .chain(discovery.duplicates.iter().map(|(path, kept)| {
+            let status = format!("duplicate of {}", kept.display());
+            (path.clone(), Status::Unused(status))
+        }))
+        .chain(
+            discovery
+                .errors
+                .iter()
+                .map(|x| (x.path().to_path_buf(), Status::Broken(x.to_string()))),
+        )
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    println!("{}", style("Actions").bold());
+    for (path, status) in &entries {
+        println!("{}", line(path.to_string_lossy().as_ref(), status));
+    }
+
+    println!("\n{}", style("Tools").bold());
+    let mut missing = 0;
+    for (tool, usage) in TOOLS {
+        let status = match which(tool) {
+            Some(path) => Status::Ok(path.to_string_lossy().to_string()),
+            None => {
+                missing += 1;
+                Status::Broken(format!("not found in PATH, needed by {}", usage))
+            }
+        };
+        println!("{}", line(tool, &status));
+    }
+
+    let broken = discovery.errors.len();
+    if broken + missing == 0 {
+        println!("\n{}", style("No problem found").green());
+        0
+    } else {
+        let s = format!(
+            "\n{} broken action file(s), {} missing tool(s)",
+            broken, missing
+        );
+        println!("{}", style(s).red());
+        1
+    }
+}
+
+fn line(name: &str, status: &Status) -> String {
+    let name = pad_str(name, 48, Alignment::Left, None);
+    match status {
+        Status::Ok(s) => format!("{} {} {}", style("✓").green(), name, style(s).dim()),
+        Status::Unused(s) => format!("{} {} {}", style("-").yellow(), name, style(s).yellow()),
+        Status::Broken(s) => format!("{} {} {}", style("✗").red(), name, style(s).red()),
+    }
+}
+
+/// Find executable `name` in `$PATH`.
+fn which(name: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(Path::new(path)))
+}
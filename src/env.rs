@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+/// Parse content of a dotenv file into `(key, value)` pairs in order of appearance.
+///
+/// Supported syntax:
+/// - `KEY=value`, optionally prefixed with `export `
+/// - blank lines and lines starting with `#`
+/// - 'single quoted' values are taken literally
+/// - "double quoted" values support `\n`, `\t`, `\"`, `\\` escapes
+/// - trailing ` # comment` after unquoted values
+///
+/// Malformed lines are skipped.
+pub fn parse_dotenv(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let key = key.trim();
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                return None;
+            }
+
+            Some((key.to_string(), dotenv_value(value.trim())))
+        })
+        .collect()
+}
+
+fn dotenv_value(raw: &str) -> String {
+    if let Some(inner) = raw.strip_prefix('\'') {
+        return inner.split('\'').next().unwrap_or("").to_string();
+    }
+
+    if let Some(inner) = raw.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(c) => value.push(c),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        return value;
+    }
+
+    match raw.find(" #") {
+        Some(i) => raw[..i].trim_end().to_string(),
+        None => raw.to_string(),
+    }
+}
+
+/// Expand `$VAR` and `${VAR}` in `text`, looking up `vars` first and then the process
+/// environment. Unknown variables expand to empty string.
+pub fn expand(text: &str, vars: &HashMap<String, String>) -> String {
+    let lookup = |name: &str| {
+        vars.get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+            .unwrap_or_default()
+    };
+
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+            result.push_str(&lookup(&name));
+            continue;
+        }
+
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                name.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        if name.is_empty() {
+            result.push('$');
+        } else {
+            result.push_str(&lookup(&name));
+        }
+    }
+
+    result
+}
+
+/// Expand leading `~` to home directory and then environment variables.
+pub fn expand_path(text: &str) -> String {
+    let text = match (text.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", home.display(), rest)
+        }
+        _ => text.to_string(),
+    };
+
+    expand(&text, &HashMap::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    #[test]
+    fn test_parse_dotenv() {
+        let text = r#"
+# comment
+RUST_LOG=debug
+export DATABASE_URL = "postgres://localhost/db\tx"
+QUOTED='$literal # not comment'
+PLAIN=value # comment
+not a pair
+"#;
+        let pairs = parse_dotenv(text);
+        let pair = |k: &str, v: &str| (k.to_string(), v.to_string());
+        assert_eq!(
+            pairs,
+            vec![
+                pair("RUST_LOG", "debug"),
+                pair("DATABASE_URL", "postgres://localhost/db\tx"),
+                pair("QUOTED", "$literal # not comment"),
+                pair("PLAIN", "value"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut vars = HashMap::new();
+        vars.insert("A".to_string(), "1".to_string());
+        assert_eq!(expand("$A-${A}x-$-$AP_NO_SUCH_VAR", &vars), "1-1x-$-");
+    }
+}
@@ -1,123 +1,1049 @@
-use std::path::Path;
-use std::process::Command;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use console::{self, style};
+use console::{self, style, Term};
 
+use crate::capture::Capture;
+use crate::discover;
+use crate::history;
+use crate::jobs;
 use crate::logging::*;
-use crate::model::Action;
+use crate::logs;
+use crate::model::{Action, Danger, Param, Seconds};
+use crate::settings::Settings;
+use crate::signal;
 
-pub fn handle(lines: &str, actions: &[Action]) {
-    let mut lines = lines.split('\n');
+/// How long an action terminated by `timeout` has to exit before it is killed.
+pub const KILL_GRACE: Duration = Duration::from_secs(5);
 
-    let key = lines.next().unwrap().trim();
-    let line2 = lines.next().unwrap();
-    let mut pair = line2.split('\t');
-    let index = pair.next().unwrap().parse::<usize>();
-    if let Err(e) = index {
-        warn!("failed to parse index from line #2: {:#?}", e);
-        return;
+/// Options of a single action execution.
+#[derive(Default)]
+pub struct Invocation {
+    /// Arguments from command line, assigned to the declared parameters in order, the rest are
+    /// passed through to the script.
+    pub args: Vec<String>,
+
+    /// Prompt for missing required parameters instead of failing.
+    pub interactive: bool,
+
+    /// Start the action as a background job instead of waiting for it, see `ap jobs`.
+    pub background: bool,
+
+    /// Print what would be executed instead of running anything.
+    pub dry_run: bool,
+}
+
+/// Everything resolved from an action and its invocation, needed to spawn the action script.
+struct Plan {
+    args: Vec<String>,
+    /// Program and all its arguments
+    command: Vec<String>,
+    vars: Vec<(String, String)>,
+    dir: PathBuf,
+}
+
+impl Plan {
+    fn new(action: &Action, invocation: &Invocation) -> Result<Plan, String> {
+        let args = arguments(action, invocation)?;
+        let mut command = action.command_line();
+        command.extend(args.iter().cloned());
+
+        Ok(Plan {
+            args,
+            command,
+            vars: action.environment()?,
+            dir: action.working_dir()?,
+        })
     }
+}
 
-    let action = &actions[index.unwrap()];
+/// How an action execution ends.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The action exited with a status code
+    Exited(i32),
+    /// The action was terminated by a signal
+    Signaled(i32),
+    /// The action was terminated for running longer than its `timeout`
+    TimedOut(Seconds),
+    /// The action could not be started
+    Failed(String),
+    /// The action required confirmation which was not given
+    Cancelled,
+}
+
+impl Outcome {
+    pub fn success(&self) -> bool {
+        *self == Outcome::Exited(0)
+    }
 
-    if !action.path.exists() {
-        println!("invalid action path: {:?}, quit", action.path);
-        return;
+    /// Exit code for ap to mirror the outcome, signals are mapped to `128 + signal` like shells.
+    pub fn code(&self) -> i32 {
+        match self {
+            Outcome::Exited(code) => *code,
+            Outcome::Signaled(signal) => 128 + signal,
+            // same as timeout(1)
+            Outcome::TimedOut(_) => 124,
+            Outcome::Failed(_) | Outcome::Cancelled => 1,
+        }
     }
 
-    debug!("pressed key: {:#?}", key);
-    debug!("select path: {:#?}", action);
+    fn from_status(status: ExitStatus) -> Outcome {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => Outcome::Exited(code),
+            (None, Some(signal)) => Outcome::Signaled(signal),
+            (None, None) => Outcome::Failed(format!("unknown exit status: {:?}", status)),
+        }
+    }
+}
 
-    match key {
-        "ctrl-e" => edit_action(action),
-        "" => run(action),
-        _ => error!("unhandled result key: {:?}", key),
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Exited(code) => write!(f, "exited with code {}", code),
+            Outcome::Signaled(signal) => write!(f, "terminated by signal {}", signal),
+            Outcome::TimedOut(timeout) => write!(f, "timed out after {}", timeout),
+            Outcome::Failed(error) => write!(f, "failed to start: {}", error),
+            Outcome::Cancelled => write!(f, "cancelled"),
+        }
     }
 }
 
-fn run(action: &Action) {
-    if let Some(ref tmux) = action.tmux {
-        respawn_tmux_pane(action, &tmux.pane_title);
-        return;
+pub fn run(action: &Action, invocation: &Invocation, settings: &Settings) -> Outcome {
+    let actions = match action.depends_on.is_empty() {
+        true => vec![],
+        false => discover::actions(&settings.discovery),
+    };
+    let chain = match dependencies(action, &actions) {
+        Ok(chain) => chain,
+        Err(error) => {
+            eprintln!("{}", style(&error).red());
+            return Outcome::Failed(error);
+        }
+    };
+
+    // dependencies take no arguments and always run in foreground
+    let dependency_invocation = Invocation {
+        interactive: invocation.interactive,
+        dry_run: invocation.dry_run,
+        ..Default::default()
+    };
+
+    let steps: Vec<_> = chain
+        .iter()
+        .map(|&dependency| (dependency, &dependency_invocation))
+        .chain(std::iter::once((action, invocation)))
+        .collect();
+    // read answers from stdin rather than the terminal, to accept those piped in
+    let mut plans = match prepare(&steps, &mut io::stdin().lock()) {
+        Ok(plans) => plans,
+        Err((action, outcome)) => {
+            report(action, &outcome);
+            return outcome;
+        }
+    };
+    let plan = plans.pop().unwrap();
+
+    for (dependency, plan) in chain.into_iter().zip(plans) {
+        let outcome = execute(dependency, &dependency_invocation, plan, false, settings);
+        if !outcome.success() {
+            let s = format!(
+                "dependency `{}` failed, skip `{}`",
+                dependency.name(),
+                action.name()
+            );
+            eprintln!("{}", style(s).red());
+            return outcome;
+        }
     }
 
-    let tip = format!("  Execute `{:?}`", &action.path);
+    if invocation.background {
+        let outcome = start_job(action, invocation, plan, settings);
+        if !outcome.success() {
+            report(action, &outcome);
+        }
+        return outcome;
+    }
+
+    execute(action, invocation, plan, true, settings)
+}
+
+/// Resolve the plan of each of `steps` in order, and ask for confirmation with answers read from
+/// `input` where required, before any of them runs, so that the chain is not cancelled halfway
+/// through. Nothing is asked for a dry run.
+///
+/// Stop at the first step failing to resolve or not confirmed, return it along with the outcome.
+fn prepare<'a, R: BufRead>(
+    steps: &[(&'a Action, &Invocation)],
+    input: &mut R,
+) -> Result<Vec<Plan>, (&'a Action, Outcome)> {
+    let mut plans = Vec::new();
+    for &(action, invocation) in steps {
+        let plan = Plan::new(action, invocation).map_err(|x| (action, Outcome::Failed(x)))?;
+        if !invocation.dry_run {
+            confirm(action, &plan, input).map_err(|x| (action, x))?;
+        }
+        plans.push(plan);
+    }
+    Ok(plans)
+}
+
+/// Start `action` as a background job as `plan` once confirmed, ignoring its `tmux` settings.
+fn start_job(action: &Action, invocation: &Invocation, plan: Plan, settings: &Settings) -> Outcome {
+    if invocation.dry_run {
+        return dry_run(action, &plan, false, true);
+    }
+
+    let job = jobs::Job {
+        path: action.path.clone(),
+        title: action.title.clone(),
+        args: plan.args,
+        cwd: std::env::current_dir().unwrap_or_default(),
+        command: plan.command,
+        vars: plan.vars,
+        dir: plan.dir,
+        time: history::now(),
+        timeout: action.timeout,
+        retries: action.retries,
+        retry_delay: action.retry_delay,
+        retry_on: action.retry_on.clone(),
+        ..Default::default()
+    };
+
+    match jobs::start(job, &settings.logs) {
+        Ok(job) => {
+            let s = format!("  Started job #{} `{}`", job.id, job.title);
+            println!("{}", style(s).green());
+            let s = format!(
+                "log: {:?}, see `ap logs {}`",
+                jobs::log_path(job.id),
+                job.id
+            );
+            println!("{}", style(s).dim());
+            Outcome::Exited(0)
+        }
+        Err(error) => Outcome::Failed(error),
+    }
+}
+
+/// Execute a single action as `plan` once confirmed, retry it as its `retries` and `retry_on`
+/// allow, and report each failed attempt.
+///
+/// Action with `tmux` settings respawns current tmux pane if `use_tmux` is true.
+fn execute(
+    action: &Action,
+    invocation: &Invocation,
+    plan: Plan,
+    use_tmux: bool,
+    settings: &Settings,
+) -> Outcome {
+    if invocation.dry_run {
+        return dry_run(action, &plan, use_tmux && action.tmux.is_some(), false);
+    }
+
+    let attempts = action.retries + 1;
+    let mut attempt = 1;
+    loop {
+        let outcome = spawn(action, &plan, use_tmux, settings);
+        if outcome.success() {
+            if attempt > 1 {
+                let s = format!(
+                    "  `{}` succeeded on attempt {}/{}",
+                    action.name(),
+                    attempt,
+                    attempts
+                );
+                println!("{}", style(s).green());
+            }
+            return outcome;
+        }
+
+        report(action, &outcome);
+        if attempt == attempts || !should_retry(&action.retry_on, &outcome) {
+            return outcome;
+        }
+
+        attempt += 1;
+        let delay = action.retry_delay.unwrap_or(Seconds(0.0));
+        let s = match delay.0 > 0.0 {
+            true => format!(
+                "  Retry `{}` in {}, attempt {}/{}",
+                action.name(),
+                delay,
+                attempt,
+                attempts
+            ),
+            false => format!(
+                "  Retry `{}`, attempt {}/{}",
+                action.name(),
+                attempt,
+                attempts
+            ),
+        };
+        println!("{}", style(s).yellow());
+        thread::sleep(Duration::from_secs_f64(delay.0));
+    }
+}
+
+/// Whether a failed `outcome` is worth a retry by `retry_on` of the action.
+pub fn should_retry(retry_on: &[i32], outcome: &Outcome) -> bool {
+    match outcome {
+        Outcome::Failed(_) | Outcome::Cancelled => false,
+        _ if !retry_on.is_empty() => retry_on.contains(&outcome.code()),
+        // interrupted or stopped by the user
+        Outcome::Signaled(libc::SIGINT | libc::SIGTERM) | Outcome::Exited(130 | 143) => false,
+        _ => true,
+    }
+}
+
+/// Wait for child process `pid` with `wait`, forwarding signals to it and terminating it once
+/// `timeout` elapses.
+pub fn wait_child<F>(pid: u32, timeout: Option<Seconds>, wait: F) -> Outcome
+where
+    F: FnOnce() -> io::Result<ExitStatus>,
+{
+    let _forwarding = signal::Forwarding::new(pid);
+    let watchdog =
+        timeout.map(|x| signal::Watchdog::new(pid, Duration::from_secs_f64(x.0), KILL_GRACE));
+
+    match (wait(), timeout) {
+        (Ok(_), Some(timeout)) if watchdog.is_some_and(|x| x.fired()) => Outcome::TimedOut(timeout),
+        (Ok(status), _) => Outcome::from_status(status),
+        (Err(error), _) => Outcome::Failed(error.to_string()),
+    }
+}
+
+/// Ask for confirmation before running `action` as `plan`, if the action requires one, with the
+/// answer read from `input`.
+fn confirm<R: BufRead>(action: &Action, plan: &Plan, input: &mut R) -> Result<(), Outcome> {
+    let question = match action.confirmation() {
+        Some(question) => question,
+        None => return Ok(()),
+    };
+
+    eprintln!("{}", style(format!("⚠ {}", question.trim())).red().bold());
+    eprintln!("  {} {}", style("cwd:    ").dim(), plan.dir.display());
+    eprintln!(
+        "  {} {}",
+        style("command:").dim(),
+        shell_words::join(&plan.command)
+    );
+
+    let name = action.name();
+    let (expected, tip) = match action.danger {
+        Danger::High => (name.as_str(), format!("Type `{}` to continue: ", name)),
+        _ => ("y", "Continue? [y/N] ".to_string()),
+    };
+    let term = Term::stderr();
+    let _ = term.write_str(&style(tip).cyan().to_string());
+
+    let mut answer = String::new();
+    let _ = input.read_line(&mut answer);
+    if answer.trim() == expected || (expected == "y" && answer.trim() == "Y") {
+        Ok(())
+    } else {
+        Err(Outcome::Cancelled)
+    }
+}
+
+fn spawn(action: &Action, plan: &Plan, use_tmux: bool, settings: &Settings) -> Outcome {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let record = |exit_code: Option<i32>, duration: f64| {
+        history::append(&history::Record {
+            path: action.path.clone(),
+            title: action.title.clone(),
+            args: plan.args.clone(),
+            cwd: cwd.clone(),
+            time: history::now() - duration as i64,
+            exit_code,
+            duration,
+        })
+    };
+
+    if let (Some(ref tmux), true) = (&action.tmux, use_tmux) {
+        // `respawn-pane` kills ap, record it beforehand
+        record(None, 0.0);
+        return respawn_tmux_pane(&tmux.pane_title, plan);
+    }
+
+    let tip = if action.is_inline() {
+        format!("  Execute `{}`", shell_words::join(&plan.command))
+    } else {
+        format!("  Execute `{:?}`", &action.path)
+    };
     println!("{}", style(tip).green());
 
-    if action.cd.unwrap_or(false) {
-        let parent_dir = action.path.parent().unwrap().parent().unwrap();
-        std::env::set_current_dir(parent_dir).unwrap();
-        let s = format!("at {:?}", parent_dir);
+    if cwd != plan.dir {
+        let s = format!("at {:?}", plan.dir);
         let s = style(s).green();
         println!("{}", s);
     }
 
-    let mut cmd = Command::new(&action.path);
+    let mut cmd = Command::new(&plan.command[0]);
+    cmd.args(&plan.command[1..])
+        .envs(plan.vars.iter().cloned())
+        .current_dir(&plan.dir);
 
-    let child = cmd.spawn();
-    if let Err(ref error) = child {
+    let log = settings
+        .logs
+        .enabled
+        .then(|| logs::create(&history::current_project(), &action.name()))
+        .flatten();
+    let (log_dir, log) = match log {
+        Some((path, file)) => (path.parent().map(Path::to_path_buf), Some(file)),
+        None => (None, None),
+    };
+
+    let start = Instant::now();
+    let result = match log {
+        Some(file) => Capture::spawn(&mut cmd, file)
+            .map(|capture| wait_child(capture.id(), action.timeout, || capture.wait())),
+        None => cmd
+            .spawn()
+            .map(|mut child| wait_child(child.id(), action.timeout, || child.wait())),
+    };
+    let outcome = result.unwrap_or_else(|error| {
         error!(
             "failed to execute action:\n  path: {:?}\n  error: {:?}",
             action.path, error
         );
-    };
+        Outcome::Failed(error.to_string())
+    });
 
-    child.unwrap().wait().unwrap();
+    record(Some(outcome.code()), start.elapsed().as_secs_f64());
+    if let Some(dir) = log_dir {
+        logs::prune(&dir, &settings.logs);
+    }
+    outcome
 }
 
-fn respawn_tmux_pane(action: &Action, title: &str) {
-    // check if is in tmux
-    if std::env::var("TMUX_PANE").is_err() {
-        return;
+/// Print a summary of failed `outcome` of `action`.
+fn report(action: &Action, outcome: &Outcome) {
+    let s = format!("✗ `{}` {}", action.name(), outcome);
+    eprintln!("{}", style(s).red().bold());
+}
+
+/// Resolve `depends_on` of `action` recursively into the order to run, excluding `action`
+/// itself.
+///
+/// Dependency names are looked up in `actions` like `ap run`, the closest match wins.
+fn dependencies<'a>(action: &'a Action, actions: &'a [Action]) -> Result<Vec<&'a Action>, String> {
+    fn visit<'a>(
+        action: &'a Action,
+        actions: &'a [Action],
+        stack: &mut Vec<&'a Action>,
+        order: &mut Vec<&'a Action>,
+    ) -> Result<(), String> {
+        if let Some(i) = stack.iter().position(|x| x.path == action.path) {
+            let cycle: Vec<_> = stack[i..]
+                .iter()
+                .chain(std::iter::once(&action))
+                .map(|x| x.name())
+                .collect();
+            return Err(format!("dependency cycle: {}", cycle.join(" -> ")));
+        }
+        if order.iter().any(|x| x.path == action.path) {
+            return Ok(());
+        }
+
+        stack.push(action);
+        for name in &action.depends_on {
+            let dependency = *discover::lookup(actions, name)
+                .first()
+                .ok_or_else(|| format!("unknown dependency `{}` of `{}`", name, action.name()))?;
+            visit(dependency, actions, stack, order)?;
+        }
+        stack.pop();
+
+        order.push(action);
+        Ok(())
     }
 
-    let pane_id = std::env::var("TMUX_PANE").unwrap();
+    let mut order = Vec::new();
+    visit(action, actions, &mut Vec::new(), &mut order)?;
+    order.pop();
+    Ok(order)
+}
+
+/// Resolve the final argument list of the action script from its parameters.
+fn arguments(action: &Action, invocation: &Invocation) -> Result<Vec<String>, String> {
+    let mut given = invocation.args.iter();
+    let mut args = Vec::new();
 
-    // set pane title
-    tmux_run(&[
-        "set-option",
-        "-p",
-        "-t",
-        &pane_id,
-        "pane-border-format",
-        &format!(" [#{{pane_index}}] {} ", title),
-    ]);
+    for param in &action.params {
+        let value = match given.next() {
+            Some(value) => param.validate(value)?,
+            None => match param.default_value() {
+                Some(value) => value,
+                None if invocation.interactive => prompt(param)?,
+                None => return Err(format!("missing required parameter `{}`", param.name)),
+            },
+        };
+        args.push(value);
+    }
 
-    // respawn pane
-    // NOTE: `respawn-pane` below would kill current process, hence code after it is not reachable
-    let path = action.path.to_str().unwrap();
-    tmux_run(&["respawn-pane", "-k", path]);
+    args.extend(given.cloned());
+    Ok(args)
 }
 
-fn tmux_run(args: &[&str]) {
-    let child = Command::new("tmux").args(args).spawn();
+/// Read value of `param` from terminal until a valid one is given.
+fn prompt(param: &Param) -> Result<String, String> {
+    let term = Term::stderr();
 
-    if let Err(ref error) = child {
-        error!(
-            "failed to run tmux command:\n  args: {:?}\n  error: {:?}",
-            args, error
-        );
+    let mut tip = param.name.clone();
+    if let Some(ref choices) = param.choices {
+        tip.push_str(&format!(" ({})", choices.join("|")));
+    }
+    if let Some(ref description) = param.description {
+        tip.push_str(&format!(" - {}", description.trim()));
+    }
+
+    loop {
+        let tip = format!("{}: ", style(&tip).cyan());
+        term.write_str(&tip).map_err(|e| e.to_string())?;
+
+        let input = term.read_line().map_err(|e| e.to_string())?;
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(format!("missing required parameter `{}`", param.name));
+        }
+
+        match param.validate(input) {
+            Ok(value) => return Ok(value),
+            Err(error) => eprintln!("{}", style(error).red()),
+        }
+    }
+}
+
+/// Print how `action` would run as `plan`, in tmux if `use_tmux`, as a background job if
+/// `background`, without running anything.
+fn dry_run(action: &Action, plan: &Plan, use_tmux: bool, background: bool) -> Outcome {
+    let s = format!("  Dry run `{}`", action.name());
+    println!("{}", style(s).green());
+
+    let pane_id = std::env::var("TMUX_PANE").ok();
+    for (name, value) in dry_run_fields(action, plan, use_tmux, background, pane_id.as_deref()) {
+        println!("    {} {}", style(format!("{:<12}", name)).dim(), value);
+    }
+
+    // as the real run would
+    if use_tmux && action.tmux.is_some() && pane_id.is_none() {
+        let outcome = Outcome::Failed("not inside a tmux session".to_string());
+        report(action, &outcome);
+        return outcome;
+    }
+    Outcome::Exited(0)
+}
+
+/// Fields printed by [`dry_run`], the tmux commands are only included with `pane_id`.
+fn dry_run_fields(
+    action: &Action,
+    plan: &Plan,
+    use_tmux: bool,
+    background: bool,
+    pane_id: Option<&str>,
+) -> Vec<(&'static str, String)> {
+    let mut fields = Vec::new();
+
+    match action.source {
+        Some(ref source) => fields.push(("script", format!("inline in {:?}", source))),
+        None => fields.push(("script", format!("{:?}", action.path))),
+    }
+    fields.push(("interpreter", interpreter(action)));
+    fields.push(("cwd", format!("{:?}", plan.dir)));
+    if plan.vars.is_empty() {
+        fields.push(("env", "-".to_string()));
+    }
+    for (key, value) in &plan.vars {
+        fields.push(("env", format!("{}={}", key, shell_words::quote(value))));
+    }
+    if plan.args.is_empty() {
+        fields.push(("args", "-".to_string()));
+    } else {
+        fields.push(("args", shell_words::join(&plan.args)));
+    }
+    fields.push(("command", shell_words::join(&plan.command)));
+
+    if let Some(question) = action.confirmation() {
+        fields.push((
+            "confirm",
+            format!("{} (danger: {})", question, action.danger.as_str()),
+        ));
+    }
+    if background {
+        fields.push(("background", "started as a job, see `ap jobs`".to_string()));
+    }
+    if let (Some(ref tmux), true, Some(pane_id)) = (&action.tmux, use_tmux, pane_id) {
+        for args in tmux_commands(&tmux.pane_title, plan, pane_id) {
+            fields.push(("tmux", format!("tmux {}", shell_words::join(&args))));
+        }
+    }
+
+    fields
+}
+
+/// Program running the action script: the shell of inline actions, or the shebang line.
+fn interpreter(action: &Action) -> String {
+    if action.is_inline() {
+        return format!("{} -c", action.shell.as_deref().unwrap_or("sh"));
+    }
+
+    let mut line = String::new();
+    let _ = fs::File::open(&action.path).and_then(|file| BufReader::new(file).read_line(&mut line));
+    match line.strip_prefix("#!") {
+        Some(shebang) => shebang.trim().to_string(),
+        None => "none, executed directly".to_string(),
+    }
+}
+
+/// Arguments of tmux commands to run `plan` in pane `pane_id`: set the pane title to `title`, then
+/// respawn the pane.
+fn tmux_commands(title: &str, plan: &Plan, pane_id: &str) -> Vec<Vec<String>> {
+    let title = vec![
+        "set-option".to_string(),
+        "-p".to_string(),
+        "-t".to_string(),
+        pane_id.to_string(),
+        "pane-border-format".to_string(),
+        format!(" [#{{pane_index}}] {} ", title),
+    ];
+
+    let mut respawn = vec![
+        "respawn-pane".to_string(),
+        "-k".to_string(),
+        "-c".to_string(),
+        plan.dir.to_string_lossy().to_string(),
+    ];
+    for (key, value) in &plan.vars {
+        respawn.push("-e".to_string());
+        respawn.push(format!("{}={}", key, value));
+    }
+    respawn.extend(plan.command.iter().cloned());
+
+    vec![title, respawn]
+}
+
+fn respawn_tmux_pane(title: &str, plan: &Plan) -> Outcome {
+    // check if is in tmux
+    let pane_id = match std::env::var("TMUX_PANE") {
+        Ok(pane_id) => pane_id,
+        Err(_) => return Outcome::Failed("not inside a tmux session".to_string()),
     };
 
-    child.unwrap().wait().unwrap();
+    // NOTE: `respawn-pane`, the last command, would kill current process, hence code after it is
+    // not reachable
+    let mut outcome = Outcome::Exited(0);
+    for args in tmux_commands(title, plan, &pane_id) {
+        let args: Vec<_> = args.iter().map(String::as_str).collect();
+        outcome = tmux_run(&args);
+    }
+    outcome
+}
+
+fn tmux_run(args: &[&str]) -> Outcome {
+    match Command::new("tmux").args(args).status() {
+        Ok(status) => Outcome::from_status(status),
+        Err(error) => {
+            error!(
+                "failed to run tmux command:\n  args: {:?}\n  error: {:?}",
+                args, error
+            );
+            Outcome::Failed(error.to_string())
+        }
+    }
 }
 
-pub fn edit_action(action: &Action) {
-    edit(&action.path);
+pub fn edit_action(action: &Action, editor: &str) {
+    match action.source {
+        Some(ref source) => edit_files(&[source.as_path()], editor),
+        None => edit(&action.path, editor),
+    }
 }
 
-pub fn edit<P: AsRef<Path>>(path: &P) {
+/// Edit action script `path` and its toml file if any.
+pub fn edit<P: AsRef<Path>>(path: &P, editor: &str) {
     let meta_path = path.as_ref().with_extension("toml");
+    if meta_path.exists() {
+        edit_files(&[path.as_ref(), &meta_path], editor);
+    } else {
+        // metadata is embedded in script as front matter
+        edit_files(&[path.as_ref()], editor);
+    }
+}
+
+fn edit_files(files: &[&Path], editor: &str) {
+    let template = editor_template(editor);
+
+    for words in editor_commands(&template, files) {
+        let status = Command::new(&words[0]).args(&words[1..]).status();
+        if let Err(error) = status {
+            error!(
+                "failed to launch editor:\n  command: {:?}\n  error: {:?}",
+                words, error
+            );
+            return;
+        }
+    }
+}
+
+/// Resolve the editor command template from the `editor` setting, `$VISUAL`, `$EDITOR`, falling
+/// back to `nvim`.
+fn editor_template(editor: &str) -> String {
+    if !editor.trim().is_empty() {
+        return editor.to_string();
+    }
+
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "nvim".to_string())
+}
+
+/// Build editor command lines to open `files`, i.e. the action script and its toml file, from
+/// `template`.
+///
+/// - `{script}` and `{meta}` in template are replaced by the paths, e.g. `code --wait {script}
+///   {meta}`, `{meta}` is dropped when there is only one file
+/// - template with only `{script}` is run once per file
+/// - template without placeholders opens all files side by side for known editors (vim family,
+///   helix, vscode), otherwise is run once per file with the path appended
+fn editor_commands(template: &str, files: &[&Path]) -> Vec<Vec<String>> {
+    let mut words = shell_words::split(template)
+        .unwrap_or_else(|_| template.split_whitespace().map(String::from).collect());
+    if words.is_empty() {
+        words.push("nvim".to_string());
+    }
+
+    let files: Vec<_> = files.iter().map(|x| x.to_string_lossy()).collect();
+    let render = |script: &str, meta: Option<&str>| -> Vec<String> {
+        words
+            .iter()
+            .filter(|x| meta.is_some() || x.as_str() != "{meta}")
+            .map(|x| {
+                x.replace("{script}", script)
+                    .replace("{meta}", meta.unwrap_or(""))
+            })
+            .collect()
+    };
+
+    if template.contains("{meta}") {
+        return vec![render(&files[0], files.get(1).map(|x| x.as_ref()))];
+    }
+
+    if template.contains("{script}") {
+        return files.iter().map(|file| render(file, None)).collect();
+    }
+
+    let program = Path::new(&words[0])
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let split = match program.as_str() {
+        "nvim" | "vim" | "vi" | "mvim" | "gvim" => Some("-O"),
+        "hx" | "helix" => Some("--vsplit"),
+        "code" | "codium" | "subl" => Some(""),
+        _ => None,
+    };
 
-    Command::new("nvim")
-        .arg("-O")
-        .arg(path.as_ref())
-        .arg(meta_path)
-        .spawn()
-        .unwrap()
-        .wait()
-        .unwrap();
-
-    // should `exec` it
-    Command::new("ap").spawn().unwrap().wait().unwrap();
+    match split {
+        Some(flag) => {
+            if !flag.is_empty() && files.len() > 1 {
+                words.push(flag.to_string());
+            }
+            words.extend(files.iter().map(|x| x.to_string()));
+            vec![words]
+        }
+        None => files
+            .iter()
+            .map(|file| {
+                let mut words = words.clone();
+                words.push(file.to_string());
+                words
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::{Confirm, Tmux};
+
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    #[test]
+    fn test_dependencies() {
+        let action = |name: &str, depends_on: &[&str]| Action {
+            path: PathBuf::from(format!("/p/.ap-actions/{}", name)),
+            depends_on: depends_on.iter().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        };
+        let actions = vec![
+            action("deploy", &["build", "test"]),
+            action("test", &["build"]),
+            action("build", &[]),
+            action("a", &["b"]),
+            action("b", &["a"]),
+            action("c", &["missing"]),
+        ];
+
+        let names = |action: &Action| -> Result<Vec<String>, String> {
+            dependencies(action, &actions)
+                .map(|chain| chain.iter().map(|x| x.name().to_string()).collect())
+        };
+
+        assert_eq!(names(&actions[0]), Ok(vec!["build".into(), "test".into()]));
+        assert_eq!(names(&actions[2]), Ok(vec![]));
+        assert_eq!(
+            names(&actions[3]),
+            Err("dependency cycle: a -> b -> a".to_string())
+        );
+        assert!(names(&actions[5]).is_err());
+    }
+
+    #[test]
+    fn test_editor_commands() {
+        let script = Path::new("/p/.ap-actions/a");
+        let meta = Path::new("/p/.ap-actions/a.toml");
+        let commands = |template| -> Vec<String> {
+            editor_commands(template, &[script, meta])
+                .into_iter()
+                .map(|words| words.join(" "))
+                .collect()
+        };
+
+        assert_eq!(
+            commands("code --wait {script} {meta}"),
+            vec!["code --wait /p/.ap-actions/a /p/.ap-actions/a.toml"]
+        );
+        assert_eq!(
+            commands("emacs -nw {script}"),
+            vec![
+                "emacs -nw /p/.ap-actions/a",
+                "emacs -nw /p/.ap-actions/a.toml"
+            ]
+        );
+        assert_eq!(
+            commands("/usr/bin/vim"),
+            vec!["/usr/bin/vim -O /p/.ap-actions/a /p/.ap-actions/a.toml"]
+        );
+        assert_eq!(
+            commands("nano"),
+            vec!["nano /p/.ap-actions/a", "nano /p/.ap-actions/a.toml"]
+        );
+
+        let source = Path::new("/p/.ap-actions/actions.toml");
+        assert_eq!(
+            editor_commands("code --wait {script} {meta}", &[source]),
+            vec![vec!["code", "--wait", "/p/.ap-actions/actions.toml"]]
+        );
+        assert_eq!(
+            editor_commands("nvim", &[source]),
+            vec![vec!["nvim", "/p/.ap-actions/actions.toml"]]
+        );
+    }
+
+    #[test]
+    fn test_tmux_commands() {
+        let plan = Plan {
+            args: vec![],
+            command: vec!["sh".to_string(), "-c".to_string(), "make".to_string()],
+            vars: vec![("A".to_string(), "1".to_string())],
+            dir: PathBuf::from("/p"),
+        };
+        let commands = tmux_commands("Build", &plan, "%3");
+        assert_eq!(commands[0][3], "%3");
+        assert_eq!(commands[0][5], " [#{pane_index}] Build ");
+        assert_eq!(
+            commands[1],
+            vec![
+                "respawn-pane",
+                "-k",
+                "-c",
+                "/p",
+                "-e",
+                "A=1",
+                "sh",
+                "-c",
+                "make"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prepare() {
+        let root = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/dir/.ap-actions"
+        ));
+        let action = |name: &str, confirm: Option<Confirm>, danger: Danger| Action {
+            path: root.join(name),
+            cd: Some(true),
+            confirm,
+            danger,
+            ..Default::default()
+        };
+        let drop = action("can-run", None, Danger::High);
+        let build = action("front-matter", None, Danger::None);
+        let deploy = action("db/migrate", Some(Confirm::Enabled(true)), Danger::None);
+
+        let invocation = Invocation::default();
+        let steps = [
+            (&drop, &invocation),
+            (&build, &invocation),
+            (&deploy, &invocation),
+        ];
+        let commands = |plans: Vec<Plan>| -> Vec<String> {
+            plans.into_iter().map(|x| x.command.join(" ")).collect()
+        };
+
+        // every question is asked up front, in the order of the chain
+        let mut input = "can-run\ny\nleft".as_bytes();
+        let plans = prepare(&steps, &mut input).unwrap();
+        assert_eq!(
+            commands(plans),
+            vec![
+                root.join("can-run").to_str().unwrap(),
+                root.join("front-matter").to_str().unwrap(),
+                root.join("db/migrate").to_str().unwrap(),
+            ]
+        );
+        assert_eq!(input, b"left");
+
+        let mut input = "can-run\nn\n".as_bytes();
+        let (action, outcome) = prepare(&steps, &mut input).err().unwrap();
+        assert_eq!(action.path, deploy.path);
+        assert!(matches!(outcome, Outcome::Cancelled));
+
+        // the name is required for high danger
+        let mut input = "y\ny\n".as_bytes();
+        let (action, _) = prepare(&steps, &mut input).err().unwrap();
+        assert_eq!(action.path, drop.path);
+        assert_eq!(input, b"y\n");
+
+        // nothing is asked for a dry run
+        let invocation = Invocation {
+            dry_run: true,
+            ..Default::default()
+        };
+        let steps = [(&drop, &invocation), (&deploy, &invocation)];
+        assert_eq!(prepare(&steps, &mut "".as_bytes()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_interpreter() {
+        let root = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/dir/.ap-actions"
+        ));
+        let action = |name: &str| Action {
+            path: root.join(name),
+            ..Default::default()
+        };
+        assert_eq!(interpreter(&action("front-matter")), "/bin/sh");
+        assert_eq!(interpreter(&action("can-run")), "none, executed directly");
+
+        let mut inline = Action {
+            source: Some(root.join("actions.toml")),
+            ..action("hello.world")
+        };
+        assert_eq!(interpreter(&inline), "sh -c");
+        inline.shell = Some("bash".to_string());
+        assert_eq!(interpreter(&inline), "bash -c");
+    }
+
+    #[test]
+    fn test_dry_run_fields() {
+        let mut action = Action {
+            path: PathBuf::from("/p/.ap-actions/build"),
+            source: Some(PathBuf::from("/p/.ap.toml")),
+            confirm: Some(Confirm::Enabled(true)),
+            tmux: Some(Tmux {
+                pane_title: "Build".to_string(),
+            }),
+            ..Default::default()
+        };
+        let plan = Plan {
+            args: vec!["a b".to_string()],
+            command: vec!["sh".to_string(), "-c".to_string(), "make".to_string()],
+            vars: vec![("A".to_string(), "1 2".to_string())],
+            dir: PathBuf::from("/p"),
+        };
+
+        let fields = dry_run_fields(&action, &plan, true, false, Some("%3"));
+        let names: Vec<_> = fields.iter().map(|x| x.0).collect();
+        assert_eq!(
+            names,
+            vec![
+                "script",
+                "interpreter",
+                "cwd",
+                "env",
+                "args",
+                "command",
+                "confirm",
+                "tmux",
+                "tmux"
+            ]
+        );
+        assert_eq!(fields[0].1, "inline in \"/p/.ap.toml\"");
+        assert_eq!(fields[3].1, "A='1 2'");
+        assert_eq!(fields[4].1, "'a b'");
+        assert_eq!(fields[5].1, "sh -c make");
+        assert_eq!(fields[6].1, "Run `build`? (danger: none)");
+        assert!(fields[8].1.starts_with("tmux respawn-pane -k -c /p"));
+
+        // outside tmux, or as a background job
+        action.confirm = None;
+        let fields = dry_run_fields(&action, &plan, false, true, None);
+        let names: Vec<_> = fields.iter().map(|x| x.0).collect();
+        assert_eq!(
+            names,
+            vec![
+                "script",
+                "interpreter",
+                "cwd",
+                "env",
+                "args",
+                "command",
+                "background"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_should_retry() {
+        assert!(should_retry(&[], &Outcome::Exited(1)));
+        assert!(should_retry(&[], &Outcome::Signaled(libc::SIGKILL)));
+        assert!(should_retry(&[], &Outcome::TimedOut(Seconds(1.0))));
+        assert!(!should_retry(&[], &Outcome::Signaled(libc::SIGINT)));
+        assert!(!should_retry(&[], &Outcome::Exited(130)));
+        assert!(!should_retry(&[], &Outcome::Failed("not found".into())));
+        assert!(!should_retry(&[], &Outcome::Cancelled));
+
+        assert!(should_retry(&[75], &Outcome::Exited(75)));
+        assert!(!should_retry(&[75], &Outcome::Exited(1)));
+        assert!(should_retry(&[124], &Outcome::TimedOut(Seconds(1.0))));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+        let outcome = wait_child(child.id(), Some(Seconds(0.2)), || child.wait());
+        assert!(matches!(outcome, Outcome::TimedOut(_)));
+        assert_eq!(outcome.code(), 124);
+
+        let mut child = Command::new("true").spawn().unwrap();
+        let outcome = wait_child(child.id(), Some(Seconds(5.0)), || child.wait());
+        assert!(outcome.success());
+    }
 }
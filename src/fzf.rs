@@ -7,7 +7,7 @@ pub struct Formatter<'a> {
 }
 
 impl<'a> Formatter<'a> {
-    pub fn new(actions: &[Action]) -> Formatter {
+    pub fn new(actions: &[Action]) -> Formatter<'_> {
         Formatter { actions }
     }
 
@@ -23,18 +23,30 @@ impl<'a> Formatter<'a> {
 impl<'a> Formatter<'a> {
     fn icon(&self, action: &Action) -> String {
         let icon = action.icon.clone().unwrap_or_else(|| "·".to_string());
-        let icon = style(icon).fg(icon_color(action)).to_string();
+        let icon = if action.shadowed {
+            style(icon).dim().to_string()
+        } else if action.confirmation().is_some() {
+            style(icon).red().to_string()
+        } else {
+            style(icon).fg(icon_color(action)).to_string()
+        };
         pad_str(&icon, 3, Alignment::Left, Some("")).to_string()
     }
 
-    fn title(&self, action: &Action) -> String {
-        let title = &action.title;
-        pad_str(title, 100, Alignment::Left, None).to_string()
-    }
-
+    /// Row of `action` in the picker, dim if shadowed, red if it asks before running.
     fn line(&self, index: usize, action: &Action) -> String {
         let icon = self.icon(action);
-        let title = &action.title;
+        let title = if action.shadowed {
+            style(&action.title).dim().to_string()
+        } else if action.confirmation().is_some() {
+            style(&action.title).red().to_string()
+        } else {
+            action.title.clone()
+        };
+        let title = match action.group {
+            Some(ref group) => format!("{}{}", style(format!("{}/", group)).cyan(), title),
+            None => title,
+        };
         let path = action.path.to_str().unwrap();
         let symlink = if action.path.is_symlink() { " " } else { "" };
 
@@ -51,8 +63,26 @@ impl<'a> Formatter<'a> {
 
 fn icon_color(action: &Action) -> Color {
     match &action.location() {
-        Global => Color::Green,
+        Global => label_color(action.label.as_deref()),
         CurrentDirectory => Color::Yellow,
         AncestorDirectory => Color::Blue,
+        TaskRunner => Color::Magenta,
+    }
+}
+
+/// Color of global actions by the label of their directory.
+fn label_color(label: Option<&str>) -> Color {
+    // orange, purple, teal, salmon, steel blue
+    const PALETTE: [u8; 5] = [208, 141, 37, 174, 67];
+
+    match label {
+        None | Some("global") => Color::Green,
+        Some("system") => Color::Cyan,
+        Some(label) => {
+            let hash = label
+                .bytes()
+                .fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize));
+            Color::Color256(PALETTE[hash % PALETTE.len()])
+        }
     }
 }
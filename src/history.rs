@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use console::{pad_str, style, Alignment};
+use serde::{Deserialize, Serialize};
+use time::{format_description, OffsetDateTime};
+
+use crate::config::{self, StatusFilter};
+use crate::discover;
+use crate::logging::*;
+use crate::model::Action;
+use crate::settings::Sort;
+
+/// An execution of an action.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Record {
+    /// Path of the action script
+    pub path: PathBuf,
+
+    #[serde(default)]
+    pub title: String,
+
+    /// Arguments passed to the action script
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Where ap was invoked
+    pub cwd: PathBuf,
+
+    /// Start time, in seconds since unix epoch
+    pub time: i64,
+
+    /// `None` if the action was handed over to tmux
+    pub exit_code: Option<i32>,
+
+    /// In seconds
+    pub duration: f64,
+}
+
+/// History file: `<state_dir>/history.jsonl`, one JSON record per line.
+pub fn path() -> PathBuf {
+    config::state_dir().join("history.jsonl")
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn append(record: &Record) {
+    let path = path();
+    if let Some(dir) = path.parent() {
+        if let Err(error) = fs::create_dir_all(dir) {
+            error!(
+                "failed to create directory:\n  path: {:?}\n  error: {:#?}",
+                dir, error
+            );
+            return;
+        }
+    }
+
+    let line = serde_json::to_string(record).unwrap() + "\n";
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(error) = result {
+        error!(
+            "failed to write history:\n  path: {:?}\n  error: {:#?}",
+            path, error
+        );
+    }
+}
+
+/// Load all records, oldest first.
+pub fn load() -> Vec<Record> {
+    let text = match fs::read_to_string(path()) {
+        Ok(text) => text,
+        Err(_) => return Vec::new(),
+    };
+
+    text.lines()
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(error) => {
+                warn!("skip invalid history line {:?}: {}", line, error);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The project containing current directory: its git root or current directory itself.
+pub fn current_project() -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    discover::git_root(&cwd).unwrap_or(cwd)
+}
+
+/// Frecency score of each action path from `records` invoked inside `project`.
+///
+/// Each run adds a weight decaying with its age: 4 within an hour, 2 within a day, 1 within a
+/// week and 0.5 for older ones.
+pub fn frecency(records: &[Record], project: &Path, now: i64) -> HashMap<PathBuf, f64> {
+    let mut scores = HashMap::new();
+
+    for record in records.iter().filter(|x| x.cwd.starts_with(project)) {
+        let age = now - record.time;
+        let weight = if age < 3600 {
+            4.0
+        } else if age < 86400 {
+            2.0
+        } else if age < 604800 {
+            1.0
+        } else {
+            0.5
+        };
+        *scores.entry(record.path.clone()).or_insert(0.0) += weight;
+    }
+
+    scores
+}
+
+/// Sort actions in place, order of discovery is kept among equal ones.
+pub fn sort(actions: &mut [Action], sort: Sort) {
+    match sort {
+        Sort::Discovery => {}
+        Sort::Alpha => actions.sort_by_key(|x| x.title.to_lowercase()),
+        Sort::Frecency => {
+            let scores = frecency(&load(), &current_project(), now());
+            let score = |action: &Action| scores.get(&action.path).copied().unwrap_or(0.0);
+            actions.sort_by(|a, b| score(b).partial_cmp(&score(a)).unwrap());
+        }
+    }
+}
+
+impl Record {
+    pub fn succeeded(&self) -> bool {
+        self.exit_code.is_none_or(|x| x == 0)
+    }
+}
+
+/// Records invoked inside `project` with exit `status`, each with its ID, i.e. 1-based index in
+/// all `records`.
+pub fn filter<'a>(
+    records: &'a [Record],
+    project: Option<&Path>,
+    status: Option<&StatusFilter>,
+) -> Vec<(usize, &'a Record)> {
+    records
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i + 1, x))
+        .filter(|(_, record)| project.is_none_or(|x| record.cwd.starts_with(x)))
+        .filter(|(_, record)| match status {
+            Some(StatusFilter::Succeeded) => record.succeeded(),
+            Some(StatusFilter::Failed) => !record.succeeded(),
+            None => true,
+        })
+        .collect()
+}
+
+/// The last record invoked inside `project`, with its ID, for `ap again`.
+pub fn last_in<'a>(records: &'a [Record], project: &Path) -> Option<(usize, &'a Record)> {
+    filter(records, Some(project), None).pop()
+}
+
+/// Print records matching the filters, each with its ID for `ap history --rerun`.
+pub fn show(project: Option<&Path>, status: Option<&StatusFilter>) {
+    let project = project.map(|x| fs::canonicalize(x).unwrap_or_else(|_| x.to_path_buf()));
+
+    let header = format!(
+        "{}{}{}{}{}{}{}",
+        cell("ID", 6),
+        cell("TIME", 18),
+        cell("EXIT", 6),
+        cell("DURATION", 10),
+        cell("TITLE", 32),
+        cell("DIRECTORY", 32),
+        "ARGS"
+    );
+    println!("{}", style(header).bold());
+
+    let records = load();
+    for (id, record) in filter(&records, project.as_deref(), status) {
+        let exit = match record.exit_code {
+            Some(code) => code.to_string(),
+            None => "tmux".to_string(),
+        };
+        let exit = cell(&exit, 6);
+        let exit = if record.succeeded() {
+            style(exit).green()
+        } else {
+            style(exit).red()
+        };
+
+        println!(
+            "{}{}{}{}{}{}{}",
+            cell(&id.to_string(), 6),
+            cell(&format_time(record.time), 18),
+            exit,
+            cell(&format_duration(record.duration), 10),
+            cell(&record.title, 32),
+            cell(&tilde(&record.cwd), 32),
+            shell_words::join(&record.args)
+        );
+    }
+}
+
+fn cell(text: &str, width: usize) -> String {
+    pad_str(text, width - 1, Alignment::Left, Some("…")).to_string() + " "
+}
+
+pub fn format_time(time: i64) -> String {
+    let format = format_description::parse_borrowed::<2>("[year]-[month]-[day] [hour]:[minute]");
+    OffsetDateTime::from_unix_timestamp(time)
+        .ok()
+        .zip(format.ok())
+        .and_then(|(time, format)| time.format(&format).ok())
+        .unwrap_or_default()
+}
+
+fn format_duration(seconds: f64) -> String {
+    if seconds < 60.0 {
+        format!("{:.1}s", seconds)
+    } else {
+        let seconds = seconds as u64;
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    }
+}
+
+/// Abbreviate home directory prefix of `path` as `~`.
+fn tilde(path: &Path) -> String {
+    match dirs::home_dir().and_then(|home| path.strip_prefix(home).ok().map(PathBuf::from)) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    fn record(path: &str, cwd: &str, exit_code: Option<i32>) -> Record {
+        Record {
+            path: PathBuf::from(path),
+            title: String::new(),
+            args: Vec::new(),
+            cwd: PathBuf::from(cwd),
+            time: 0,
+            exit_code,
+            duration: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let records = vec![
+            record("/p/.ap-actions/a", "/p", Some(0)),
+            record("/q/.ap-actions/b", "/q", Some(1)),
+            record("/p/.ap-actions/c", "/p/sub", Some(2)),
+            record("/p/.ap-actions/a", "/pp", None),
+        ];
+        let ids = |filtered: Vec<(usize, &Record)>| -> Vec<_> {
+            filtered.into_iter().map(|(id, _)| id).collect()
+        };
+
+        assert_eq!(ids(filter(&records, None, None)), vec![1, 2, 3, 4]);
+        assert_eq!(
+            ids(filter(&records, Some(Path::new("/p")), None)),
+            vec![1, 3]
+        );
+        assert_eq!(
+            ids(filter(&records, None, Some(&StatusFilter::Failed))),
+            vec![2, 3]
+        );
+        assert_eq!(
+            ids(filter(&records, None, Some(&StatusFilter::Succeeded))),
+            vec![1, 4]
+        );
+    }
+
+    #[test]
+    fn test_last_in() {
+        let records = vec![
+            record("/p/.ap-actions/a", "/p", Some(0)),
+            record("/p/.ap-actions/b", "/p/sub", Some(0)),
+            record("/q/.ap-actions/c", "/q", Some(0)),
+        ];
+
+        let (id, last) = last_in(&records, Path::new("/p")).unwrap();
+        assert_eq!(id, 2);
+        assert_eq!(last.path, Path::new("/p/.ap-actions/b"));
+        assert_eq!(last_in(&records, Path::new("/p/sub")).map(|x| x.0), Some(2));
+        assert_eq!(last_in(&records, Path::new("/r")), None);
+    }
+
+    #[test]
+    fn test_frecency() {
+        let record = |path: &str, cwd: &str, age: i64| Record {
+            path: PathBuf::from(path),
+            title: String::new(),
+            args: Vec::new(),
+            cwd: PathBuf::from(cwd),
+            time: 1_000_000 - age,
+            exit_code: Some(0),
+            duration: 1.0,
+        };
+        let records = vec![
+            record("/p/.ap-actions/a", "/p", 10),
+            record("/p/.ap-actions/a", "/p/sub", 7200),
+            record("/p/.ap-actions/b", "/p", 10_000_000),
+            record("/p/.ap-actions/b", "/q", 10),
+        ];
+
+        let scores = frecency(&records, Path::new("/p"), 1_000_000);
+        assert_eq!(scores.get(Path::new("/p/.ap-actions/a")), Some(&6.0));
+        assert_eq!(scores.get(Path::new("/p/.ap-actions/b")), Some(&0.5));
+    }
+}
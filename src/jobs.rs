@@ -0,0 +1,524 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use console::{pad_str, style, Alignment};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::executor::{self, Outcome};
+use crate::history;
+use crate::logging::*;
+use crate::model::Seconds;
+use crate::settings::LogSettings;
+use crate::signal;
+
+/// An action started in background, supervised by a detached ap process.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Job {
+    pub id: u32,
+
+    /// Path of the action script
+    pub path: PathBuf,
+
+    pub title: String,
+
+    /// Arguments passed to the action script
+    pub args: Vec<String>,
+
+    /// Where ap was invoked
+    pub cwd: PathBuf,
+
+    /// Program and all its arguments
+    pub command: Vec<String>,
+
+    pub vars: Vec<(String, String)>,
+
+    /// Working directory of the action
+    pub dir: PathBuf,
+
+    /// Start time, in seconds since unix epoch
+    pub time: i64,
+
+    /// Pid of the supervisor, which leads the process group of the job
+    pub pid: Option<i32>,
+
+    /// Pid of the running attempt of the action, which leads a process group of its own
+    #[serde(default)]
+    pub child: Option<i32>,
+
+    /// Exit code of the action, set when it ends
+    pub exit_code: Option<i32>,
+
+    /// In seconds, set when the action ends
+    pub duration: Option<f64>,
+
+    /// Retry policy of the action, see `Action`
+    #[serde(default)]
+    pub timeout: Option<Seconds>,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default)]
+    pub retry_delay: Option<Seconds>,
+    #[serde(default)]
+    pub retry_on: Vec<i32>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Running,
+    Exited(i32),
+    /// The supervisor died before the action ended
+    Lost,
+}
+
+/// Jobs directory: `<state_dir>/jobs`, holding `{id}.json` and `{id}.log` of each job.
+pub fn dir() -> PathBuf {
+    config::state_dir().join("jobs")
+}
+
+pub fn log_path(id: u32) -> PathBuf {
+    dir().join(format!("{}.log", id))
+}
+
+fn meta_path(id: u32) -> PathBuf {
+    dir().join(format!("{}.json", id))
+}
+
+pub fn load(id: u32) -> Option<Job> {
+    let text = fs::read_to_string(meta_path(id)).ok()?;
+    if text.is_empty() {
+        // ID reserved by `start`, not saved yet
+        return None;
+    }
+    match serde_json::from_str(&text) {
+        Ok(job) => Some(job),
+        Err(error) => {
+            warn!("invalid job file {:?}: {}", meta_path(id), error);
+            None
+        }
+    }
+}
+
+/// All jobs, oldest first.
+pub fn load_all() -> Vec<Job> {
+    let entries = match fs::read_dir(dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut jobs: Vec<_> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            load(path.file_stem()?.to_str()?.parse().ok()?)
+        })
+        .collect();
+    jobs.sort_by_key(|x| x.id);
+    jobs
+}
+
+fn save(job: &Job) -> io::Result<()> {
+    // write then rename, `ap jobs` may read it at any time
+    let path = meta_path(job.id);
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, serde_json::to_string_pretty(job).unwrap())?;
+    fs::rename(temp, path)
+}
+
+impl Job {
+    pub fn status(&self) -> Status {
+        if let Some(code) = self.exit_code {
+            return Status::Exited(code);
+        }
+
+        match self.pid {
+            Some(pid) if unsafe { libc::kill(pid, 0) } == 0 => Status::Running,
+            // the supervisor has not recorded its pid yet
+            None if history::now() - self.time < 5 => Status::Running,
+            _ => Status::Lost,
+        }
+    }
+}
+
+/// Register `job` and start its supervisor in a new process group, return the job with its ID.
+///
+/// Jobs ended longer than `keep_days` of `settings` ago are removed first.
+pub fn start(mut job: Job, settings: &LogSettings) -> Result<Job, String> {
+    fs::create_dir_all(dir()).map_err(|e| format!("failed to create {:?}: {}", dir(), e))?;
+    prune(settings);
+
+    job.id = reserve_id(load_all().last().map_or(1, |x| x.id + 1))
+        .map_err(|e| format!("failed to allocate job ID: {}", e))?;
+    save(&job).map_err(|e| format!("failed to save job: {}", e))?;
+    File::create(log_path(job.id)).map_err(|e| format!("failed to create log: {}", e))?;
+
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    Command::new(exe)
+        .arg("supervise")
+        .arg(job.id.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| format!("failed to start supervisor: {}", e))?;
+
+    Ok(job)
+}
+
+/// Reserve the first free job ID from `id` by creating its empty job file, so that concurrent
+/// `start`s never get the same ID.
+fn reserve_id(mut id: u32) -> io::Result<u32> {
+    loop {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(meta_path(id))
+        {
+            Ok(_) => return Ok(id),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => id += 1,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Run the action of job `id` with output redirected to its log, then record how it ends.
+///
+/// This is the body of the detached supervisor process started by [`start`].
+pub fn supervise(id: u32) -> i32 {
+    let mut job = match load(id) {
+        Some(job) => job,
+        None => return 1,
+    };
+    job.pid = Some(std::process::id() as i32);
+    if let Err(error) = save(&job) {
+        error!("failed to save job #{}: {}", id, error);
+    }
+
+    // `ap stop` signals the process group of the supervisor, which forwards the signal to the
+    // action, outlive the action to record its exit code
+    signal::catch();
+
+    let start = Instant::now();
+    let code = match run(&mut job) {
+        Ok(code) => code,
+        Err(error) => {
+            if let Ok(mut log) = OpenOptions::new().append(true).open(log_path(id)) {
+                let _ = writeln!(log, "ap: failed to start job: {}", error);
+            }
+            127
+        }
+    };
+    let duration = start.elapsed().as_secs_f64();
+
+    job.exit_code = Some(code);
+    job.duration = Some(duration);
+    if let Err(error) = save(&job) {
+        error!("failed to save job #{}: {}", id, error);
+    }
+
+    history::append(&history::Record {
+        path: job.path.clone(),
+        title: job.title.clone(),
+        args: job.args.clone(),
+        cwd: job.cwd.clone(),
+        time: job.time,
+        exit_code: Some(code),
+        duration,
+    });
+    code
+}
+
+/// Run the action of `job` with its timeout and retry policy, noting each failed attempt in the
+/// log, return the exit code of the last attempt.
+///
+/// Stop retrying once the job is stopped, including during `retry_delay`.
+fn run(job: &mut Job) -> io::Result<i32> {
+    let mut log = OpenOptions::new().append(true).open(log_path(job.id))?;
+    let attempts = job.retries + 1;
+    let mut attempt = 1;
+
+    loop {
+        // in its own process group, which is terminated as a whole on timeout
+        let mut child = Command::new(&job.command[0])
+            .args(&job.command[1..])
+            .envs(job.vars.iter().cloned())
+            .current_dir(&job.dir)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log.try_clone()?)
+            .process_group(0)
+            .spawn()?;
+        job.child = Some(child.id() as i32);
+        if let Err(error) = save(job) {
+            error!("failed to save job #{}: {}", job.id, error);
+        }
+
+        let outcome = executor::wait_child(child.id(), job.timeout, || child.wait());
+        // `Forwarding` of `wait_child` restores the default handlers
+        signal::catch();
+
+        job.child = None;
+        if let Err(error) = save(job) {
+            error!("failed to save job #{}: {}", job.id, error);
+        }
+
+        if outcome.success() {
+            return Ok(0);
+        }
+        let _ = writeln!(log, "ap: attempt {}/{} {}", attempt, attempts, outcome);
+        let retry = executor::should_retry(&job.retry_on, &outcome) && !signal::stop_requested();
+        if attempt == attempts || !retry {
+            return Ok(match outcome {
+                Outcome::Failed(_) => 127,
+                outcome => outcome.code(),
+            });
+        }
+
+        attempt += 1;
+        let delay = Duration::from_secs_f64(job.retry_delay.unwrap_or(Seconds(0.0)).0);
+        let start = Instant::now();
+        while start.elapsed() < delay {
+            if signal::stop_requested() {
+                let _ = writeln!(log, "ap: stopped, skip the remaining attempts");
+                return Ok(outcome.code());
+            }
+            thread::sleep(
+                delay
+                    .saturating_sub(start.elapsed())
+                    .min(Duration::from_millis(100)),
+            );
+        }
+    }
+}
+
+/// Print all jobs with their status.
+pub fn show() {
+    let header = format!(
+        "{}{}{}{}{}",
+        cell("ID", 6),
+        cell("STATUS", 10),
+        cell("PID", 8),
+        cell("STARTED", 18),
+        "TITLE"
+    );
+    println!("{}", style(header).bold());
+
+    for job in load_all() {
+        let status = match job.status() {
+            Status::Running => style(cell("running", 10)).yellow(),
+            Status::Exited(0) => style(cell("exit 0", 10)).green(),
+            Status::Exited(code) => style(cell(&format!("exit {}", code), 10)).red(),
+            Status::Lost => style(cell("lost", 10)).dim(),
+        };
+        let pid = job.pid.map(|x| x.to_string()).unwrap_or_default();
+
+        println!(
+            "{}{}{}{}{}",
+            cell(&job.id.to_string(), 6),
+            status,
+            cell(&pid, 8),
+            cell(&history::format_time(job.time), 18),
+            job.title
+        );
+    }
+}
+
+fn cell(text: &str, width: usize) -> String {
+    pad_str(text, width - 1, Alignment::Left, Some("…")).to_string() + " "
+}
+
+fn find(id: u32) -> Result<Job, i32> {
+    load(id).ok_or_else(|| {
+        let s = format!("no job with ID {}", id);
+        eprintln!("{}", style(s).red());
+        1
+    })
+}
+
+/// Print output of job `id`, keep printing new output until it ends if `follow`, then return its
+/// exit code.
+pub fn logs(id: u32, follow: bool) -> i32 {
+    let job = match find(id) {
+        Ok(job) => job,
+        Err(code) => return code,
+    };
+
+    let mut file = match File::open(log_path(id)) {
+        Ok(file) => file,
+        Err(error) => {
+            let s = format!("failed to open log of job #{}: {}", id, error);
+            eprintln!("{}", style(s).red());
+            return 1;
+        }
+    };
+
+    let mut stdout = io::stdout();
+    let mut position = 0;
+    loop {
+        let running = follow && load(id).unwrap_or_else(|| job.clone()).status() == Status::Running;
+
+        let mut buffer = Vec::new();
+        if file.seek(SeekFrom::Start(position)).is_ok() {
+            let _ = file.read_to_end(&mut buffer);
+        }
+        position += buffer.len() as u64;
+        let _ = stdout.write_all(&buffer);
+        let _ = stdout.flush();
+
+        if !running {
+            break;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+
+    if !follow {
+        return 0;
+    }
+
+    // mirror the exit code of the job like a foreground run
+    match load(id).unwrap_or(job).status() {
+        Status::Exited(code) => {
+            let s = format!("job #{} exited with code {}", id, code);
+            eprintln!("{}", style(s).dim());
+            code
+        }
+        _ => 1,
+    }
+}
+
+/// Terminate job `id` with SIGTERM, which the supervisor forwards to the action, then kill the
+/// action with SIGKILL if the job is still running after [`executor::KILL_GRACE`].
+pub fn stop(id: u32) -> i32 {
+    let job = match find(id) {
+        Ok(job) => job,
+        Err(code) => return code,
+    };
+
+    match (job.status(), job.pid) {
+        (Status::Running, Some(pid)) => {
+            if unsafe { libc::kill(-pid, libc::SIGTERM) } != 0 {
+                let error = io::Error::last_os_error();
+                let s = format!("failed to stop job #{}: {}", id, error);
+                eprintln!("{}", style(s).red());
+                return 1;
+            }
+            let s = format!("  Sent SIGTERM to job #{} `{}`", id, job.title);
+            println!("{}", style(s).green());
+
+            let start = Instant::now();
+            while start.elapsed() < executor::KILL_GRACE {
+                thread::sleep(Duration::from_millis(100));
+                let job = load(id).unwrap_or_default();
+                if job.status() != Status::Running {
+                    return 0;
+                }
+            }
+
+            if let Some(child) = load(id).and_then(|x| x.child) {
+                signal::terminate(child, libc::SIGKILL);
+                let s = format!("  Sent SIGKILL to job #{} `{}`", id, job.title);
+                println!("{}", style(s).yellow());
+            }
+            0
+        }
+        _ => {
+            let s = format!("job #{} is not running", id);
+            eprintln!("{}", style(s).red());
+            1
+        }
+    }
+}
+
+/// Remove jobs, along with their logs, ended longer than `keep_days` of `settings` ago.
+pub fn prune(settings: &LogSettings) {
+    if settings.keep_days == 0 {
+        return;
+    }
+    let limit = history::now() - i64::from(settings.keep_days) * 86400;
+
+    for job in load_all() {
+        let end = job.time + job.duration.unwrap_or(0.0) as i64;
+        if job.status() == Status::Running || end > limit {
+            continue;
+        }
+        for path in [log_path(job.id), meta_path(job.id)] {
+            if let Err(error) = fs::remove_file(&path) {
+                warn!("failed to remove {:?}: {}", path, error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    #[test]
+    fn test_status() {
+        let job = |pid: Option<i32>, exit_code: Option<i32>, age: i64| Job {
+            pid,
+            exit_code,
+            time: history::now() - age,
+            ..Default::default()
+        };
+        let me = Some(std::process::id() as i32);
+
+        assert_eq!(job(me, Some(2), 0).status(), Status::Exited(2));
+        assert_eq!(job(me, None, 100).status(), Status::Running);
+        assert_eq!(job(None, None, 0).status(), Status::Running);
+        assert_eq!(job(None, None, 100).status(), Status::Lost);
+    }
+
+    #[test]
+    fn test_prune() {
+        config::use_test_state_dir();
+        fs::create_dir_all(dir()).unwrap();
+
+        let day = 86400;
+        let me = Some(std::process::id() as i32);
+        let jobs = [
+            // ended 10 days ago
+            (1, me, Some(0), 50, Some(40.0 * day as f64)),
+            // still running
+            (2, me, None, 50, None),
+            // ended 50 days ago
+            (3, me, Some(0), 50, Some(1.0)),
+            (4, me, Some(1), 1, Some(1.0)),
+            // lost 50 days ago
+            (5, None, None, 50, None),
+        ];
+        for (id, pid, exit_code, age, duration) in jobs {
+            let job = Job {
+                id,
+                pid,
+                exit_code,
+                duration,
+                time: history::now() - age * day,
+                ..Default::default()
+            };
+            save(&job).unwrap();
+            File::create(log_path(id)).unwrap();
+        }
+
+        prune(&LogSettings {
+            keep_days: 30,
+            ..Default::default()
+        });
+        let ids: Vec<_> = load_all().iter().map(|x| x.id).collect();
+        assert_eq!(ids, vec![1, 2, 4]);
+        assert!(!log_path(3).exists());
+        assert!(!log_path(5).exists());
+        assert!(log_path(4).exists());
+    }
+}
@@ -1,9 +1,19 @@
 pub mod config;
 pub mod logging;
 pub mod run;
+pub mod settings;
 
+mod capture;
 mod discover;
+mod doctor;
+mod env;
 mod executor;
 mod fzf;
+mod history;
+mod jobs;
+mod list;
+mod logs;
 mod model;
 mod preview;
+mod provider;
+mod signal;
@@ -0,0 +1,205 @@
+use console::{self, pad_str, style, Alignment};
+
+use crate::config::ListFormat;
+use crate::discover;
+use crate::model::{Action, ActionInfo, ActionLoadError};
+use crate::settings::Settings;
+
+/// Print all discovered actions to stdout in the given `format`, only those in `group` and its
+/// subgroups if given.
+pub fn list(format: &ListFormat, group: Option<&str>, settings: &Settings) {
+    let discovery = discover::scan(&settings.discovery);
+
+    // non-executable files are commonly notes or helpers rather than broken actions
+    let broken = discovery
+        .errors
+        .iter()
+        .filter(|x| !matches!(x, ActionLoadError::NotExecutable(_)))
+        .count();
+    if broken > 0 {
+        let s = format!(
+            "{} action file(s) failed to load, run `ap doctor` for details",
+            broken
+        );
+        eprintln!("{}", style(s).yellow());
+    }
+
+    let mut actions = discovery.actions;
+    if let Some(group) = group {
+        let group = group.trim_end_matches('/');
+        actions.retain(|x| {
+            x.group.as_deref().is_some_and(|x| {
+                x == group || x.strip_prefix(group).is_some_and(|x| x.starts_with('/'))
+            })
+        });
+    }
+
+    let output = match format {
+        ListFormat::Table => table(&actions),
+        ListFormat::Json => json(&actions),
+        ListFormat::Tsv => tsv(&actions),
+    };
+    print!("{}", output);
+}
+
+fn table(actions: &[Action]) -> String {
+    let mut lines = Vec::new();
+    let header = format!(
+        "{}{}{}{}{}{}",
+        cell("", 3),
+        cell("NAME", 24),
+        cell("TITLE", 32),
+        cell("LOCATION", 20),
+        cell("TMUX", 16),
+        "PATH"
+    );
+    lines.push(style(header).bold().to_string());
+
+    for action in actions {
+        let info = ActionInfo::from(action);
+        let tmux = action.tmux.as_ref().map(|x| x.pane_title.as_str());
+        let location = match action.label {
+            Some(ref label) => format!("{} ({})", info.location.as_str(), label),
+            None => info.location.as_str().to_string(),
+        };
+        let cd = if action.cd.unwrap_or(false) {
+            " (cd)"
+        } else {
+            ""
+        };
+
+        lines.push(format!(
+            "{}{}{}{}{}{}{}",
+            pad_str(
+                action.icon.as_deref().unwrap_or("·"),
+                3,
+                Alignment::Left,
+                None
+            ),
+            cell(&info.name, 24),
+            cell(&action.title, 32),
+            cell(&location, 20),
+            cell(tmux.unwrap_or("-"), 16),
+            action.path.display(),
+            cd
+        ));
+    }
+
+    lines.iter().map(|x| format!("{}\n", x)).collect()
+}
+
+fn cell(text: &str, width: usize) -> String {
+    pad_str(text, width - 1, Alignment::Left, Some("…")).to_string() + " "
+}
+
+fn json(actions: &[Action]) -> String {
+    let infos: Vec<_> = actions.iter().map(ActionInfo::from).collect();
+    format!("{}\n", serde_json::to_string_pretty(&infos).unwrap())
+}
+
+/// One action per line, fields in order:
+/// name, title, icon, description, location, tmux pane title, cd, path
+fn tsv(actions: &[Action]) -> String {
+    let mut output = String::new();
+    for action in actions {
+        let info = ActionInfo::from(action);
+        let fields = [
+            info.name.as_str(),
+            &action.title,
+            action.icon.as_deref().unwrap_or(""),
+            action.description.as_deref().unwrap_or(""),
+            info.location.as_str(),
+            action.tmux.as_ref().map_or("", |x| x.pane_title.as_str()),
+            if action.cd.unwrap_or(false) {
+                "true"
+            } else {
+                "false"
+            },
+            action.path.to_str().unwrap_or(""),
+        ];
+
+        let line = fields
+            .iter()
+            .map(|x| escape(x))
+            .collect::<Vec<_>>()
+            .join("\t");
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
+
+/// Escape characters which would break the line / field structure of TSV.
+fn escape(field: &str) -> String {
+    field
+        .trim_end()
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::model::Tmux;
+
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    fn actions() -> Vec<Action> {
+        vec![
+            Action {
+                path: PathBuf::from("/p/.ap-actions/db/migrate"),
+                group: Some("db".to_string()),
+                title: "Migrate\tdatabase".to_string(),
+                description: Some("Run\npending migrations\\ ".to_string()),
+                cd: Some(true),
+                ..Default::default()
+            },
+            Action {
+                path: PathBuf::from("/p/.ap-actions/watch"),
+                title: "Watch".to_string(),
+                icon: Some("👀".to_string()),
+                tmux: Some(Tmux {
+                    pane_title: "watch".to_string(),
+                }),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_tsv() {
+        assert_eq!(
+            tsv(&actions()),
+            "db/migrate\tMigrate\\tdatabase\t\tRun\\npending migrations\\\\\tancestor-directory\t\ttrue\t/p/.ap-actions/db/migrate\n\
+             watch\tWatch\t👀\t\tancestor-directory\twatch\tfalse\t/p/.ap-actions/watch\n"
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        console::set_colors_enabled(false);
+        let output = table(&actions());
+        let lines: Vec<_> = output.lines().map(|x| x.trim_end()).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("   NAME "));
+        assert!(lines[1].starts_with("·  db/migrate "));
+        assert!(lines[1].ends_with("/p/.ap-actions/db/migrate (cd)"));
+        assert!(lines[2].contains(" watch "));
+    }
+
+    #[test]
+    fn test_json() {
+        let value: serde_json::Value = serde_json::from_str(&json(&actions())).unwrap();
+        let infos = value.as_array().unwrap();
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0]["name"], "db/migrate");
+        assert_eq!(infos[0]["title"], "Migrate\tdatabase");
+        assert_eq!(infos[0]["location"], "ancestor-directory");
+        assert_eq!(infos[1]["tmux"]["pane_title"], "watch");
+    }
+}
@@ -0,0 +1,255 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use console::style;
+use time::{format_description, OffsetDateTime};
+
+use crate::config;
+use crate::history;
+use crate::logging::*;
+use crate::settings::LogSettings;
+
+/// Logs directory: `<state_dir>/logs`, holding `<project>/<action>/<time>.log` of each run.
+pub fn dir() -> PathBuf {
+    config::state_dir().join("logs")
+}
+
+/// Directory of run logs of action `name` in `project`, both escaped into a single path
+/// component.
+pub fn action_dir(project: &Path, name: &str) -> PathBuf {
+    let project = project.to_string_lossy();
+    dir()
+        .join(escape(project.trim_start_matches('/')))
+        .join(escape(name))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('%', "%25").replace('/', "%2F")
+}
+
+/// Create a new log file for a run of action `name` in `project`, named after the start time.
+pub fn create(project: &Path, name: &str) -> Option<(PathBuf, File)> {
+    let dir = action_dir(project, name);
+    if let Err(error) = fs::create_dir_all(&dir) {
+        error!(
+            "failed to create directory:\n  path: {:?}\n  error: {:#?}",
+            dir, error
+        );
+        return None;
+    }
+
+    let format = format_description::parse_borrowed::<2>(
+        "[year][month][day]-[hour][minute][second].[subsecond digits:3]",
+    )
+    .ok()?;
+    let stamp = OffsetDateTime::now_utc().format(&format).ok()?;
+
+    create_file(&dir, &stamp)
+        .map_err(|(path, error)| {
+            error!(
+                "failed to create log file:\n  path: {:?}\n  error: {:#?}",
+                path, error
+            );
+        })
+        .ok()
+}
+
+/// Create log file `{stamp}.log` in `dir`, or `{stamp}~{n}.log` if it exists.
+fn create_file(dir: &Path, stamp: &str) -> Result<(PathBuf, File), (PathBuf, io::Error)> {
+    // file names sort in time order, `~` sorts after `.log` for the unlikely collisions
+    let mut i = 0;
+    loop {
+        let path = match i {
+            0 => dir.join(format!("{}.log", stamp)),
+            i => dir.join(format!("{}~{}.log", stamp, i)),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => i += 1,
+            Err(error) => return Err((path, error)),
+        }
+    }
+}
+
+/// Log files in `dir`, oldest first.
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|x| x.ok().map(|x| x.path()))
+                .filter(|x| x.extension().is_some_and(|x| x == "log"))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+/// Remove log files beyond the retention limits of `settings`: all but the last `keep_runs` in
+/// `dir` of the action just run, and those older than `keep_days` of any action in any project.
+pub fn prune(dir: &Path, settings: &LogSettings) {
+    let keep = settings.keep_runs as usize;
+    if keep > 0 {
+        let files = files(dir);
+        for file in &files[..files.len().saturating_sub(keep)] {
+            remove(file);
+        }
+    }
+
+    if settings.keep_days > 0 {
+        let max_age = u64::from(settings.keep_days) * 86400;
+        for project in subdirs(&self::dir()) {
+            for file in subdirs(&project).iter().flat_map(|x| files(x)) {
+                let expired = fs::metadata(&file)
+                    .and_then(|x| x.modified())
+                    .ok()
+                    .and_then(|x| x.elapsed().ok())
+                    .is_some_and(|x| x.as_secs() > max_age);
+                if expired {
+                    remove(&file);
+                }
+            }
+        }
+    }
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|x| x.ok().map(|x| x.path()))
+                .filter(|x| x.is_dir())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn remove(file: &Path) {
+    if let Err(error) = fs::remove_file(file) {
+        warn!("failed to remove log file {:?}: {}", file, error);
+    }
+}
+
+/// Print logs of the last `last` runs of action `name` in current project, oldest first.
+pub fn show(name: &str, last: usize) -> i32 {
+    let dir = action_dir(&history::current_project(), name);
+    if !print(&dir, last, &mut io::stdout()) {
+        let s = format!("no logs of `{}` in current project", name);
+        eprintln!("{}", style(s).red());
+        return 1;
+    }
+    0
+}
+
+/// Print the last `last` log files in `dir` to `out`, oldest first, each after a line of its
+/// path, return `false` if there is none.
+fn print<W: Write>(dir: &Path, last: usize, out: &mut W) -> bool {
+    let files = files(dir);
+    for file in &files[files.len().saturating_sub(last)..] {
+        let s = format!("── {} ──", file.display());
+        let _ = writeln!(out, "{}", style(s).dim());
+        match fs::read(file) {
+            Ok(content) => {
+                let _ = out.write_all(&content);
+            }
+            Err(error) => eprintln!("{}", style(error).red()),
+        }
+    }
+    !files.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, SystemTime};
+
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    #[test]
+    fn test_action_dir() {
+        let dir = action_dir(Path::new("/home/me/proj"), "db/migrate");
+        assert_eq!(
+            dir.strip_prefix(super::dir()).unwrap(),
+            Path::new("home%2Fme%2Fproj/db%2Fmigrate")
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        config::use_test_state_dir();
+        let create = |project: &str, name: &str, age_days: u64| {
+            let (path, file) = create(Path::new(project), name).unwrap();
+            let age = Duration::from_secs(age_days * 86400);
+            file.set_modified(SystemTime::now() - age).unwrap();
+            path
+        };
+
+        // logs of other actions, in another project, which are not run again
+        let stale = create("/prune/a", "stale", 40);
+        let fresh = create("/prune/a", "fresh", 20);
+        let runs: Vec<_> = (0..3).map(|_| create("/prune/b", "run", 0)).collect();
+
+        let dir = action_dir(Path::new("/prune/b"), "run");
+        let settings = LogSettings {
+            enabled: true,
+            keep_runs: 2,
+            keep_days: 30,
+        };
+        prune(&dir, &settings);
+        assert!(!stale.exists());
+        assert!(fresh.exists());
+        assert_eq!(files(&dir), runs[1..].to_vec());
+    }
+
+    #[test]
+    fn test_create() {
+        config::use_test_state_dir();
+        let dir = action_dir(Path::new("/create"), "run");
+        fs::create_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = (0..3)
+            .map(|_| create_file(&dir, "20240101-000000.000").unwrap().0)
+            .map(|x| x.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "20240101-000000.000.log",
+                "20240101-000000.000~1.log",
+                "20240101-000000.000~2.log",
+            ]
+        );
+
+        let (path, _) = create(Path::new("/create"), "run").unwrap();
+        assert_eq!(files(&dir).last(), Some(&path));
+    }
+
+    #[test]
+    fn test_print() {
+        config::use_test_state_dir();
+        console::set_colors_enabled(false);
+        let dir = action_dir(Path::new("/print"), "run");
+        let mut out = Vec::new();
+        assert!(!print(&dir, 1, &mut out));
+        assert!(out.is_empty());
+
+        let paths: Vec<_> = (1..=3)
+            .map(|i| {
+                let (path, mut file) = create(Path::new("/print"), "run").unwrap();
+                writeln!(file, "run {}", i).unwrap();
+                path
+            })
+            .collect();
+
+        assert!(print(&dir, 2, &mut out));
+        let expected = format!(
+            "── {} ──\nrun 2\n── {} ──\nrun 3\n",
+            paths[1].display(),
+            paths[2].display()
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+}
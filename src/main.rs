@@ -4,6 +4,9 @@ use ap::run::run;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     logging::init();
-    run(Config::load());
+    let code = run(Config::load());
+    if code != 0 {
+        std::process::exit(code);
+    }
     Ok(())
 }
@@ -1,27 +1,232 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
+use std::io::{self, Read};
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use indexmap::IndexMap;
 use is_executable::is_executable;
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer, Serialize};
 
 use crate::config;
+use crate::discover;
+use crate::env;
 use crate::logging::*;
+use crate::provider;
 
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
 pub enum ActionLocation {
     Global,
     CurrentDirectory,
     AncestorDirectory,
+    /// Synthesized from a task runner file, e.g. a Makefile
+    TaskRunner,
 }
 
-#[derive(Default, Debug, Deserialize, PartialEq)]
+impl ActionLocation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActionLocation::Global => "global",
+            ActionLocation::CurrentDirectory => "current-directory",
+            ActionLocation::AncestorDirectory => "ancestor-directory",
+            ActionLocation::TaskRunner => "task-runner",
+        }
+    }
+}
+
+#[derive(Default, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Tmux {
     pub pane_title: String,
 }
 
-#[derive(Default, Debug, Deserialize, PartialEq)]
+/// A duration in seconds, written in toml as a number of seconds or a string with one of the
+/// units `ms`, `s`, `m` and `h`, e.g. `90`, `"1.5s"`, `"5m"`.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct Seconds(pub f64);
+
+impl Seconds {
+    pub fn parse(text: &str) -> Result<Seconds, String> {
+        let text = text.trim();
+        let split = text
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+
+        let scale = match unit.trim() {
+            "ms" => 0.001,
+            "" | "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            unit => return Err(format!("invalid duration unit `{}` in {:?}", unit, text)),
+        };
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("invalid duration {:?}", text))?;
+        Ok(Seconds(number * scale))
+    }
+}
+
+impl fmt::Display for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}s", self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Seconds {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Seconds, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Integer(u64),
+            Float(f64),
+            Text(String),
+        }
+
+        let seconds = match Raw::deserialize(deserializer)? {
+            Raw::Integer(x) => Seconds(x as f64),
+            Raw::Float(x) => Seconds(x),
+            Raw::Text(text) => Seconds::parse(&text).map_err(de::Error::custom)?,
+        };
+        // also too large for a `Duration`
+        if Duration::try_from_secs_f64(seconds.0).is_err() {
+            return Err(de::Error::custom(format!("invalid duration {}", seconds.0)));
+        }
+        Ok(seconds)
+    }
+}
+
+/// `confirm` of an action, `true` or a custom message to ask before running it.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum Confirm {
+    Enabled(bool),
+    Message(String),
+}
+
+/// How destructive an action is, actions with a danger level ask for confirmation before
+/// running.
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Danger {
+    #[default]
+    None,
+    /// Confirmed by typing `y`
+    Low,
+    /// Confirmed by typing the action name
+    High,
+}
+
+impl Danger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Danger::None => "none",
+            Danger::Low => "low",
+            Danger::High => "high",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    #[default]
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+impl ParamType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParamType::String => "string",
+            ParamType::Int => "int",
+            ParamType::Float => "float",
+            ParamType::Bool => "bool",
+        }
+    }
+}
+
+/// A named parameter of an action, passed to the script as a positional argument in the order of
+/// declaration.
+#[derive(Default, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Param {
+    pub name: String,
+
+    #[serde(default, rename = "type")]
+    pub kind: ParamType,
+
+    /// Parameter without a default value is required.
+    #[serde(default)]
+    pub default: Option<toml::Value>,
+
+    #[serde(default)]
+    pub choices: Option<Vec<String>>,
+
+    pub description: Option<String>,
+}
+
+impl Param {
+    pub fn is_required(&self) -> bool {
+        self.default.is_none()
+    }
+
+    pub fn default_value(&self) -> Option<String> {
+        self.default.as_ref().map(|value| match value {
+            toml::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+    }
+
+    /// Check `value` against type and choices of the parameter, return the normalized value.
+    pub fn validate(&self, value: &str) -> Result<String, String> {
+        if let Some(ref choices) = self.choices {
+            if !choices.iter().any(|x| x == value) {
+                return Err(format!(
+                    "invalid value `{}` for parameter `{}`, expecting one of: {}",
+                    value,
+                    self.name,
+                    choices.join(", ")
+                ));
+            }
+        }
+
+        let valid = match self.kind {
+            ParamType::String => true,
+            ParamType::Int => value.parse::<i64>().is_ok(),
+            ParamType::Float => value.parse::<f64>().is_ok(),
+            ParamType::Bool => {
+                return match value.to_lowercase().as_str() {
+                    "true" | "yes" | "y" | "1" => Ok("true".to_string()),
+                    "false" | "no" | "n" | "0" => Ok("false".to_string()),
+                    _ => Err(format!(
+                        "invalid value `{}` for bool parameter `{}`",
+                        value, self.name
+                    )),
+                };
+            }
+        };
+
+        if valid {
+            Ok(value.to_string())
+        } else {
+            Err(format!(
+                "invalid value `{}` for {} parameter `{}`",
+                value,
+                self.kind.as_str(),
+                self.name
+            ))
+        }
+    }
+}
+
+#[derive(Default, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Action {
-    #[serde(skip)]
+    #[serde(skip_deserializing)]
     pub path: PathBuf,
 
     #[serde(default)]
@@ -38,55 +243,520 @@ pub struct Action {
     ///
     /// e.g. for action script in /a/b/c/.ap-actions/1.zsh, cd to /a/b/c then
     /// execute the script
+    ///
+    /// Superseded by `cwd`, `cd = true` is the same as `cwd = "project"`
     #[serde(default)]
     pub cd: Option<bool>,
+
+    /// Working directory of the action script, one of:
+    /// - "project": the directory containing `.ap-actions`
+    /// - "invocation": where ap was started, the default
+    /// - "git-root": the git work tree containing the invocation directory
+    /// - a path, `~` and environment variables are expanded, relative path is resolved against
+    ///   the project directory
+    #[serde(default)]
+    pub cwd: Option<String>,
+
+    #[serde(default)]
+    pub params: Vec<Param>,
+
+    /// Names of actions to run before this one, stop at the first failure
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
+    /// Extra environment variables, values can reference variables from `env_file` or the
+    /// process environment as `$VAR` or `${VAR}`, including those defined above in `env`
+    #[serde(default)]
+    pub env: IndexMap<String, String>,
+
+    /// Dotenv file loaded before `env`, relative to the directory containing the action
+    #[serde(default)]
+    pub env_file: Option<PathBuf>,
+
+    /// Command body of inline actions, run as `{shell} -c {command}`
+    #[serde(default, alias = "script")]
+    pub command: Option<String>,
+
+    /// Shell command to run inline actions with, defaults to `sh`
+    #[serde(default)]
+    pub shell: Option<String>,
+
+    /// Terminate the action if it runs longer than this, with SIGTERM then SIGKILL if it does
+    /// not exit in time
+    #[serde(default)]
+    pub timeout: Option<Seconds>,
+
+    /// Times to run the action again after a failed run
+    #[serde(default)]
+    pub retries: u32,
+
+    /// Pause between a failed run and its retry
+    #[serde(default)]
+    pub retry_delay: Option<Seconds>,
+
+    /// Exit codes worth a retry, any failure except interruption by ctrl-c if empty, a timed out
+    /// run counts as code 124
+    #[serde(default)]
+    pub retry_on: Vec<i32>,
+
+    /// Ask before running the action, `true` or a message to show
+    #[serde(default)]
+    pub confirm: Option<Confirm>,
+
+    /// One of "none", "low" and "high", a dangerous action always asks before running, and
+    /// "high" requires typing the action name instead of `y`
+    #[serde(default)]
+    pub danger: Danger,
+
+    /// The toml file defining this action, for inline actions only
+    #[serde(skip_deserializing)]
+    pub source: Option<PathBuf>,
+
+    /// Name of the task runner provider this action is synthesized by, e.g. `make`
+    #[serde(skip_deserializing)]
+    pub provider: Option<String>,
+
+    /// Group of the action, i.e. path of its subdirectory relative to `.ap-actions`, e.g. `db`
+    /// for `.ap-actions/db/migrate`
+    #[serde(skip_deserializing)]
+    pub group: Option<String>,
+
+    /// Label of the global action directory this action is found in, e.g. `global` or `system`
+    #[serde(skip_deserializing)]
+    pub label: Option<String>,
+
+    /// Whether an action of the same name in a closer scope takes precedence over this one
+    #[serde(skip_deserializing)]
+    pub shadowed: bool,
+
+    /// Names of symlinks to this action, which are dropped as duplicates but still accepted by
+    /// `ap run`
+    #[serde(skip_deserializing)]
+    pub aliases: Vec<String>,
+}
+
+/// Max number of bytes to read from the head of script looking for front matter.
+const FRONT_MATTER_LIMIT: u64 = 16 * 1024;
+
+/// Extract toml metadata embedded in the leading comment lines of script `path`.
+fn front_matter(path: &Path) -> Option<String> {
+    let mut head = Vec::new();
+    fs::File::open(path)
+        .ok()?
+        .take(FRONT_MATTER_LIMIT)
+        .read_to_end(&mut head)
+        .ok()?;
+    parse_front_matter(&String::from_utf8_lossy(&head))
+}
+
+/// Collect toml metadata from leading comment lines (`#` or `//`) of script `text`, either lines
+/// prefixed with `ap:`, or lines enclosed by `---` lines:
+///
+/// ```sh
+/// #!/bin/sh
+/// # ap: title = "Build"
+/// # ---
+/// # icon = "B"
+/// # cwd = "project"
+/// # ---
+/// ```
+fn parse_front_matter(text: &str) -> Option<String> {
+    let mut toml = Vec::new();
+    let mut in_block = false;
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_start();
+        if (i == 0 && line.starts_with("#!")) || line.trim().is_empty() {
+            continue;
+        }
+
+        let comment = match line.strip_prefix('#').or_else(|| line.strip_prefix("//")) {
+            Some(comment) => comment.strip_prefix(' ').unwrap_or(comment),
+            None => break,
+        };
+
+        if comment.trim_end() == "---" {
+            in_block = !in_block;
+        } else if in_block {
+            toml.push(comment);
+        } else if let Some(entry) = comment.strip_prefix("ap:") {
+            toml.push(entry.trim());
+        }
+    }
+
+    if toml.is_empty() {
+        None
+    } else {
+        Some(toml.join("\n"))
+    }
+}
+
+/// Why a candidate file fails to load as an action.
+#[derive(Debug)]
+pub enum ActionLoadError {
+    /// The script lacks the executable bit
+    NotExecutable(PathBuf),
+
+    /// Neither `{stem}.toml` nor front matter is found for the script
+    MissingMetadata(PathBuf),
+
+    Unreadable {
+        path: PathBuf,
+        error: io::Error,
+    },
+
+    /// The toml file, or front matter of a script, fails to parse
+    InvalidToml {
+        path: PathBuf,
+        front_matter: bool,
+        /// Error of the toml parser, with line and column
+        message: String,
+    },
+
+    /// An inline action entry without `command`
+    MissingCommand {
+        path: PathBuf,
+        name: String,
+    },
+
+    /// A `{stem}.toml` file with no script of the same stem beside it
+    OrphanMetadata(PathBuf),
+
+    /// No action is found at the (virtual) path
+    NotFound(PathBuf),
+}
+
+impl ActionLoadError {
+    /// The file failed to load.
+    pub fn path(&self) -> &Path {
+        match self {
+            ActionLoadError::NotExecutable(path)
+            | ActionLoadError::MissingMetadata(path)
+            | ActionLoadError::OrphanMetadata(path)
+            | ActionLoadError::NotFound(path)
+            | ActionLoadError::Unreadable { path, .. }
+            | ActionLoadError::InvalidToml { path, .. }
+            | ActionLoadError::MissingCommand { path, .. } => path,
+        }
+    }
+
+    /// Log the error, non-executable files are common (e.g. README) and only logged for debug.
+    pub fn log(&self) {
+        match self {
+            ActionLoadError::NotExecutable(path) => debug!("skip non-executable file: {:?}", path),
+            _ => error!(
+                "failed to load action\n  path: {:#?}\n  error: {}",
+                self.path(),
+                self
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ActionLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActionLoadError::NotExecutable(_) => write!(f, "not executable, missing `chmod +x`?"),
+            ActionLoadError::MissingMetadata(path) => {
+                let meta = path.with_extension("toml");
+                let meta = meta.file_name().unwrap_or_default().to_string_lossy();
+                write!(f, "neither `{}` nor front matter found", meta)
+            }
+            ActionLoadError::Unreadable { error, .. } => write!(f, "failed to read: {}", error),
+            ActionLoadError::InvalidToml {
+                front_matter: true,
+                message,
+                ..
+            } => write!(f, "invalid front matter: {}", message),
+            ActionLoadError::InvalidToml { message, .. } => write!(f, "invalid toml: {}", message),
+            ActionLoadError::MissingCommand { name, .. } => {
+                write!(f, "inline action `{}` has no `command`", name)
+            }
+            ActionLoadError::OrphanMetadata(_) => write!(f, "no script found for this toml file"),
+            ActionLoadError::NotFound(_) => write!(f, "no action found"),
+        }
+    }
+}
+
+impl std::error::Error for ActionLoadError {}
+
+/// An `[[action]]` entry of `actions.toml` or `.ap.toml`.
+#[derive(Deserialize)]
+struct InlineAction {
+    name: String,
+
+    #[serde(flatten)]
+    action: Action,
+}
+
+#[derive(Deserialize)]
+struct InlineFile {
+    #[serde(default)]
+    action: Vec<InlineAction>,
+}
+
+/// Name of files defining inline actions, under `.ap-actions` directory.
+pub const INLINE_FILE: &str = "actions.toml";
+
+/// Name of files defining inline actions, under project root.
+pub const PROJECT_INLINE_FILE: &str = ".ap.toml";
+
+/// Group of the action at `path`, i.e. path of its directory relative to the nearest
+/// `.ap-actions` or global action directory above it, including the `extra` ones as in
+/// [`config::action_dirs`], `None` if directly under one.
+fn group_of(path: &Path, extra: &[String]) -> Option<String> {
+    let dir = path.parent()?;
+    let global_dirs = config::action_dirs(extra);
+    let base = dir.ancestors().find(|x| {
+        x.file_name() == Some(OsStr::new(".ap-actions"))
+            || global_dirs.iter().any(|global| global.path == *x)
+    })?;
+
+    let group = dir.strip_prefix(base).ok()?.to_str()?;
+    (!group.is_empty()).then(|| group.to_string())
 }
 
 impl Action {
     /// Construct a `Action` from input `path` and its corresponding toml file.
     ///
     /// Argument `path` must be executable and has a toml file named `{stem}.toml` under the
-    /// same directory
-    pub fn load_from<P: AsRef<Path>>(path: P) -> Option<Action> {
+    /// same directory, or metadata embedded in its leading comment lines, see
+    /// [`parse_front_matter`]
+    ///
+    /// If `path` does not exist, it is looked up among inline actions defined by `actions.toml`
+    /// in the same directory or `.ap.toml` in the parent directory, then among tasks synthesized
+    /// by task runner providers.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Action, ActionLoadError> {
         let path = path.as_ref();
-        trace!("Action::load_from: {:?}", &path);
+        trace!("Action::load: {:?}", &path);
+
+        if !path.exists() {
+            let not_found = || ActionLoadError::NotFound(path.to_path_buf());
+            let dir = path.parent().ok_or_else(not_found)?;
+            let name = path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .ok_or_else(not_found)?;
+            let project_file = dir.parent().map(|x| x.join(PROJECT_INLINE_FILE));
+            return std::iter::once(dir.join(INLINE_FILE))
+                .chain(project_file)
+                .flat_map(|file| Action::load_inline(&file, dir))
+                .find(|action| action.name() == name)
+                .or_else(|| provider::find(path))
+                .ok_or_else(not_found);
+        }
 
-        if !is_executable(&path) {
-            debug!("skip non-executable file: {:?}", &path);
-            return None;
+        if !is_executable(path) {
+            return Err(ActionLoadError::NotExecutable(path.to_path_buf()));
         }
 
         let meta_path = path.with_extension("toml");
-        let text = fs::read_to_string(&meta_path);
-        if let Err(e) = text {
-            error!(
-                "failed to read file\n  path: {:#?}\n  error: {:#?}",
-                &meta_path, &e
-            );
-            return None;
-        }
-        let text = text.unwrap();
-
-        let action = toml::from_str::<Action>(&text);
-        if let Err(error) = action {
-            error!(
-                "failed to parse toml file\n  path: {:#?}\n  error: {:#?}",
-                &meta_path, &error
-            );
-            return None;
-        }
-
-        let mut action = action.unwrap();
+        let (meta_path, text) = match fs::read_to_string(&meta_path) {
+            Ok(text) => (meta_path, text),
+            Err(_) => match front_matter(path) {
+                Some(text) => (path.to_path_buf(), text),
+                None => return Err(ActionLoadError::MissingMetadata(path.to_path_buf())),
+            },
+        };
+
+        let mut action =
+            toml::from_str::<Action>(&text).map_err(|error| ActionLoadError::InvalidToml {
+                front_matter: meta_path == path,
+                path: meta_path,
+                message: error.to_string(),
+            })?;
         action.path = path.to_path_buf();
 
         info!("found action: {:?}", action.path);
+        Ok(action)
+    }
+
+    /// Same as [`Action::load`], with the error logged, for actions loaded by path outside of
+    /// discovery, which derives the group from the location of the action under `.ap-actions`
+    /// or one of the global action directories, `action_dirs` being the extra ones from settings.
+    pub fn load_from<P: AsRef<Path>>(path: P, action_dirs: &[String]) -> Option<Action> {
+        let mut action = Action::load(path).map_err(|error| error.log()).ok()?;
+        if action.group.is_none() {
+            action.group = group_of(&action.path, action_dirs);
+        }
         Some(action)
     }
 
+    /// Load inline actions defined by `[[action]]` entries in toml file `path`.
+    ///
+    /// Each inline action is given a virtual path `{dir}/{name}` as if it were a script under the
+    /// `.ap-actions` directory `dir`. Invalid entries are logged and skipped.
+    pub fn load_inline(path: &Path, dir: &Path) -> Vec<Action> {
+        Action::load_inline_entries(path, dir)
+            .into_iter()
+            .filter_map(|entry| entry.map_err(|error| error.log()).ok())
+            .collect()
+    }
+
+    /// Same as [`Action::load_inline`], but keeps the errors, one for each invalid entry or a
+    /// single one for the whole file. A missing file yields nothing.
+    pub fn load_inline_entries(path: &Path, dir: &Path) -> Vec<Result<Action, ActionLoadError>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Vec::new(),
+            Err(error) => {
+                return vec![Err(ActionLoadError::Unreadable {
+                    path: path.to_path_buf(),
+                    error,
+                })]
+            }
+        };
+
+        let file = match toml::from_str::<InlineFile>(&text) {
+            Ok(file) => file,
+            Err(error) => {
+                return vec![Err(ActionLoadError::InvalidToml {
+                    path: path.to_path_buf(),
+                    front_matter: false,
+                    message: error.to_string(),
+                })]
+            }
+        };
+
+        file.action
+            .into_iter()
+            .map(|entry| {
+                let mut action = entry.action;
+                if action.command.is_none() {
+                    return Err(ActionLoadError::MissingCommand {
+                        path: path.to_path_buf(),
+                        name: entry.name,
+                    });
+                }
+
+                action.path = dir.join(&entry.name);
+                action.source = Some(path.to_path_buf());
+                info!("found inline action: {:?}", action.path);
+                Ok(action)
+            })
+            .collect()
+    }
+
+    /// The question to ask before running the action, `None` if it needs no confirmation.
+    pub fn confirmation(&self) -> Option<String> {
+        match (&self.confirm, self.danger) {
+            (Some(Confirm::Message(message)), _) => Some(message.clone()),
+            (Some(Confirm::Enabled(true)), _) | (_, Danger::Low | Danger::High) => {
+                Some(format!("Run `{}`?", self.name()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.source.is_some()
+    }
+
+    /// Name of the action, i.e. the file stem of the action script, or the name of inline action,
+    /// prefixed by its group if any, e.g. `db/migrate`.
+    pub fn name(&self) -> String {
+        let name = if self.is_inline() {
+            self.path.file_name()
+        } else {
+            self.path.file_stem()
+        };
+        let name = name.and_then(|x| x.to_str()).unwrap_or("");
+
+        match self.group {
+            Some(ref group) => format!("{}/{}", group, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Program and leading arguments to execute the action.
+    pub fn command_line(&self) -> Vec<String> {
+        match (&self.command, self.is_inline()) {
+            (Some(command), true) => {
+                let shell = self.shell.as_deref().unwrap_or("sh");
+                let mut words = shell_words::split(shell).unwrap_or_else(|_| vec![shell.into()]);
+                words.push("-c".to_string());
+                words.push(command.clone());
+                // `$0` of the command
+                words.push(self.name().to_string());
+                words
+            }
+            _ => vec![self.path.to_string_lossy().to_string()],
+        }
+    }
+
+    /// Directory relative paths in action settings are resolved against: the `.ap-actions`
+    /// directory, or the directory of the toml file defining an inline action.
+    pub fn base_dir(&self) -> &Path {
+        match self.source {
+            Some(ref source) => source.parent().unwrap(),
+            None => self.path.parent().unwrap(),
+        }
+    }
+
+    /// Environment variables to set for the action, entries from `env_file` followed by `env`.
+    pub fn environment(&self) -> Result<Vec<(String, String)>, String> {
+        let mut vars = Vec::new();
+
+        if let Some(ref file) = self.env_file {
+            let path = self.base_dir().join(file);
+            let text = fs::read_to_string(&path)
+                .map_err(|e| format!("failed to read env file {:?}: {}", path, e))?;
+            vars.extend(env::parse_dotenv(&text));
+        }
+
+        let mut known: HashMap<_, _> = vars.iter().cloned().collect();
+        for (key, value) in &self.env {
+            let value = env::expand(value, &known);
+            known.insert(key.clone(), value.clone());
+            vars.push((key.clone(), value));
+        }
+
+        Ok(vars)
+    }
+
+    /// The `.ap-actions` (or global actions) directory the action is found in, above the
+    /// subdirectories of its group.
+    pub fn actions_dir(&self) -> &Path {
+        let depth = self.group.as_ref().map_or(0, |x| x.split('/').count());
+        self.path.ancestors().nth(depth + 1).unwrap()
+    }
+
+    /// The directory containing the `.ap-actions` directory of the action.
+    pub fn project_dir(&self) -> &Path {
+        self.actions_dir().parent().unwrap()
+    }
+
+    /// Resolve the working directory to execute the action script in.
+    pub fn working_dir(&self) -> Result<PathBuf, String> {
+        let cwd = match (self.cwd.as_deref(), self.cd) {
+            (Some(cwd), _) => cwd,
+            (None, Some(true)) => "project",
+            _ => "invocation",
+        };
+
+        let invocation_dir =
+            || std::env::current_dir().map_err(|e| format!("invalid current directory: {}", e));
+
+        let dir = match cwd {
+            "project" => self.project_dir().to_path_buf(),
+            "invocation" => invocation_dir()?,
+            "git-root" => discover::git_root(invocation_dir()?)
+                .ok_or_else(|| "not inside a git work tree".to_string())?,
+            path => self.project_dir().join(env::expand_path(path)),
+        };
+
+        if !dir.is_dir() {
+            return Err(format!("working directory {:?} does not exist", dir));
+        }
+        Ok(dir)
+    }
+
     pub fn location(&self) -> ActionLocation {
-        let path = self.path.parent().unwrap();
+        let path = self.actions_dir();
 
-        if path == config::global_actions_dir().as_path() {
+        if self.provider.is_some() {
+            ActionLocation::TaskRunner
+        } else if self.label.is_some() || path == config::global_actions_dir().as_path() {
             ActionLocation::Global
         } else if path.parent().unwrap() == std::env::current_dir().unwrap().as_path() {
             ActionLocation::CurrentDirectory
@@ -96,6 +766,27 @@ impl Action {
     }
 }
 
+/// Serializable view of an [`Action`] with its derived properties, used by `ap list`.
+#[derive(Debug, Serialize)]
+pub struct ActionInfo<'a> {
+    pub name: String,
+
+    #[serde(flatten)]
+    pub action: &'a Action,
+
+    pub location: ActionLocation,
+}
+
+impl<'a> From<&'a Action> for ActionInfo<'a> {
+    fn from(action: &'a Action) -> Self {
+        ActionInfo {
+            name: action.name(),
+            action,
+            location: action.location(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -105,8 +796,8 @@ mod tests {
 
     #[test]
     fn test_action_load() {
-        let path = "/Users/mudox/Develop/Rust/ap/tests/dir/.ap-actions/can-run";
-        let left = Action::load_from(path).unwrap();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dir/.ap-actions/can-run");
+        let left = Action::load_from(path, &[]).unwrap();
 
         let right = Action {
             path: PathBuf::from(path),
@@ -117,5 +808,178 @@ mod tests {
         };
 
         assert_eq!(left, right);
+
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/dir/.ap-actions/can-not-run"
+        );
+        let error = Action::load(path).unwrap_err();
+        assert!(matches!(error, ActionLoadError::NotExecutable(_)));
+        assert_eq!(error.path(), Path::new(path));
+    }
+
+    #[test]
+    fn test_load_inline() {
+        let dir = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/dir/.ap-actions"
+        ));
+        let actions = Action::load_inline(&dir.join(INLINE_FILE), dir);
+        assert_eq!(actions.len(), 2);
+
+        let action = &actions[0];
+        assert_eq!(action.name(), "hello.world");
+        assert_eq!(action.title, "Inline hello");
+        assert_eq!(
+            action.command_line(),
+            vec!["bash", "-eu", "-c", "echo hello", "hello.world"]
+        );
+        assert_eq!(actions[1].params[0].name, "who");
+
+        let loaded = Action::load_from(dir.join("hello.world"), &[]).unwrap();
+        assert_eq!(&loaded, action);
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let text = "#!/bin/sh\n\n# ap: title = \"Lines\"\n#ap: cd = true\necho '# ap: x = 1'\n";
+        assert_eq!(
+            parse_front_matter(text),
+            Some("title = \"Lines\"\ncd = true".to_string())
+        );
+        assert_eq!(parse_front_matter("#!/bin/sh\necho hi\n"), None);
+
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/dir/.ap-actions/front-matter"
+        );
+        let action = Action::load_from(path, &[]).unwrap();
+        assert_eq!(action.title, "Title of front-matter");
+        assert_eq!(action.icon, Some("fm".to_string()));
+        assert_eq!(action.depends_on, vec!["can-run".to_string()]);
+    }
+
+    #[test]
+    fn test_working_dir() {
+        let root = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dir"));
+        let mut action = Action {
+            path: root.join(".ap-actions/can-run"),
+            cd: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(action.working_dir(), Ok(root.to_path_buf()));
+
+        action.cwd = Some("d0/d1".to_string());
+        assert_eq!(action.working_dir(), Ok(root.join("d0/d1")));
+
+        action.cwd = Some(root.join("d0").to_str().unwrap().to_string());
+        assert_eq!(action.working_dir(), Ok(root.join("d0")));
+
+        action.cwd = Some("no-such-dir".to_string());
+        assert!(action.working_dir().is_err());
+    }
+
+    #[test]
+    fn test_load_group() {
+        let root = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dir"));
+
+        // loaded by path, outside of discovery
+        let mut action = Action::load_from(root.join(".ap-actions/db/migrate"), &[]).unwrap();
+        assert_eq!(action.group.as_deref(), Some("db"));
+        assert_eq!(action.name(), "db/migrate");
+        action.cwd = Some("project".to_string());
+        assert_eq!(action.working_dir(), Ok(root.to_path_buf()));
+
+        let action = Action::load_from(root.join(".ap-actions/can-run"), &[]).unwrap();
+        assert_eq!(action.group, None);
+
+        // under an extra action directory from settings
+        let path = Path::new("/opt/tools/db/backup/full");
+        assert_eq!(group_of(path, &[]), None);
+        let extra = ["tools=/opt/tools".to_string()];
+        assert_eq!(group_of(path, &extra).as_deref(), Some("db/backup"));
+    }
+
+    #[test]
+    fn test_param_validate() {
+        let param = Param {
+            name: "level".to_string(),
+            kind: ParamType::Int,
+            choices: Some(vec!["1".to_string(), "2".to_string()]),
+            ..Default::default()
+        };
+        assert!(param.is_required());
+        assert_eq!(param.validate("2"), Ok("2".to_string()));
+        assert!(param.validate("3").is_err());
+
+        let param = Param {
+            name: "force".to_string(),
+            kind: ParamType::Bool,
+            default: Some(toml::Value::Boolean(false)),
+            ..Default::default()
+        };
+        assert_eq!(param.default_value(), Some("false".to_string()));
+        assert_eq!(param.validate("yes"), Ok("true".to_string()));
+        assert!(param.validate("maybe").is_err());
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let action: Action = toml::from_str(
+            "title = \"t\"\ntimeout = \"1.5m\"\nretries = 2\nretry_delay = 3\nretry_on = [1, 124]",
+        )
+        .unwrap();
+        assert_eq!(action.timeout, Some(Seconds(90.0)));
+        assert_eq!(action.retry_delay, Some(Seconds(3.0)));
+        assert_eq!(action.retries, 2);
+        assert_eq!(action.retry_on, vec![1, 124]);
+
+        assert_eq!(Seconds::parse("250ms"), Ok(Seconds(0.25)));
+        assert_eq!(Seconds::parse("2 h"), Ok(Seconds(7200.0)));
+        assert!(Seconds::parse("1d").is_err());
+        assert!(toml::from_str::<Action>("title = \"t\"\ntimeout = -1").is_err());
+        assert!(toml::from_str::<Action>("title = \"t\"\ntimeout = 1e20").is_err());
+        assert!(
+            toml::from_str::<Action>("title = \"t\"\nretry_delay = \"99999999999999999h\"")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_confirmation() {
+        let action = |text: &str| {
+            let mut action: Action = toml::from_str(&format!("title = \"t\"\n{}", text)).unwrap();
+            action.path = PathBuf::from("/p/.ap-actions/drop");
+            action
+        };
+
+        assert_eq!(action("").confirmation(), None);
+        assert_eq!(action("confirm = false").confirmation(), None);
+        assert_eq!(
+            action("confirm = true").confirmation(),
+            Some("Run `drop`?".to_string())
+        );
+        assert_eq!(
+            action("danger = \"high\"").confirmation(),
+            Some("Run `drop`?".to_string())
+        );
+        assert_eq!(
+            action("confirm = \"Drop it?\"\ndanger = \"low\"").confirmation(),
+            Some("Drop it?".to_string())
+        );
+    }
+
+    #[test]
+    fn test_environment() {
+        let mut action: Action =
+            toml::from_str("title = \"t\"\n[env]\nZ_FIRST = \"z\"\nA_SECOND = \"${Z_FIRST}a\"")
+                .unwrap();
+        action.path = PathBuf::from("/p/.ap-actions/env");
+
+        let pair = |k: &str, v: &str| (k.to_string(), v.to_string());
+        assert_eq!(
+            action.environment(),
+            Ok(vec![pair("Z_FIRST", "z"), pair("A_SECOND", "za")])
+        );
     }
 }
@@ -1,42 +1,95 @@
 use std::error::Error;
 use std::fs;
-use std::process::Command;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
 use std::str;
 
 use console::{self, pad_str, style, Alignment};
 use time::{format_description, OffsetDateTime};
 
+use crate::config;
 use crate::logging::*;
-use crate::model::Action;
+use crate::model::{Action, Param};
+use crate::settings::Settings;
 
 /// Generate and print preview for action to stdout.
-pub fn preview(path: &str) {
-    let action = Action::load_from(path);
+pub fn preview(path: &str, settings: &Settings) {
+    let action = Action::load_from(path, &settings.discovery.action_dirs);
     if action.is_none() {
         return;
     }
     let action = action.unwrap();
 
-    // path
-    println!("{}", line("Path", path));
-
-    // file type
-    if let Some(content) = filetype(path) {
-        println!("{}", line("File Type", &content));
+    if let Some(ref source) = action.source {
+        // inline action
+        println!("{}", line("Defined In", &source.to_string_lossy()));
+        let shell = action.shell.as_deref().unwrap_or("sh");
+        println!("{}", line("Shell", shell));
+    } else {
+        // path
+        println!("{}", line("Path", path));
+
+        // file type
+        if let Some(content) = filetype(path) {
+            println!("{}", line("File Type", &content));
+        }
+
+        // creation time
+        if let Ok(ctime) = ctime(path) {
+            println!("{}", line("Created", &ctime));
+        }
     }
 
-    // creation time
-    if let Ok(ctime) = ctime(path) {
-        println!("{}", line("Created", &ctime));
+    // global action directory
+    let dir = Path::new(path).parent();
+    let global = config::action_dirs(&settings.discovery.action_dirs)
+        .into_iter()
+        .find(|x| Some(x.path.as_path()) == dir);
+    if let Some(global) = global {
+        let source = format!("{} ({})", global.label, global.path.display());
+        println!("{}", line("Source", &source));
     }
 
     // description
-    if let Some(desc) = action.description {
-        println!("{}", line("Description", &desc));
+    if let Some(ref desc) = action.description {
+        println!("{}", line("Description", desc));
+    }
+
+    // dependencies
+    if !action.depends_on.is_empty() {
+        println!("{}", line("Depends On", &action.depends_on.join(", ")));
+    }
+
+    // working directory
+    match action.working_dir() {
+        Ok(dir) => println!("{}", line("Working Dir", &dir.to_string_lossy())),
+        Err(error) => println!("{}", line("Working Dir", &error)),
     }
 
-    if let Ok(bat) = bat(path) {
-        println!("{}", &bat)
+    // parameters
+    for (i, param) in action.params.iter().enumerate() {
+        let title = if i == 0 { "Parameters" } else { "" };
+        println!("{}", line(title, &param_summary(param)));
+    }
+
+    // environment
+    match action.environment() {
+        Ok(vars) => {
+            for (i, (key, value)) in vars.iter().enumerate() {
+                let title = if i == 0 { "Environment" } else { "" };
+                println!("{}", line(title, &format!("{}={}", key, value)));
+            }
+        }
+        Err(error) => println!("{}", line("Environment", &error)),
+    }
+
+    let content = match (action.is_inline(), &action.command) {
+        (true, Some(command)) => bat_text(command).or_else(|_| Ok(command.clone())),
+        _ => bat(path),
+    };
+    if let Ok(content) = content {
+        println!("{}", &content)
     }
 }
 
@@ -50,6 +103,19 @@ fn line(title: &str, content: &str) -> String {
     format!("{}{}", title, content)
 }
 
+fn param_summary(param: &Param) -> String {
+    let mut s = format!("{} <{}>", param.name, param.kind.as_str());
+    if param.is_required() {
+        s.push_str(" required");
+    } else if let Some(value) = param.default_value() {
+        s.push_str(&format!(" = {:?}", value));
+    }
+    if let Some(ref choices) = param.choices {
+        s.push_str(&format!(" in [{}]", choices.join(", ")));
+    }
+    s
+}
+
 fn filetype(path: &str) -> Option<String> {
     let output = Command::new("file").arg("--brief").arg(path).output();
     if let Err(error) = output {
@@ -68,23 +134,39 @@ fn filetype(path: &str) -> Option<String> {
 fn ctime(path: &str) -> Result<String, Box<dyn Error>> {
     let meta = fs::metadata(path)?;
     let ctime: OffsetDateTime = meta.created()?.into();
-    let format = format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]")?;
+    let format =
+        format_description::parse_borrowed::<2>("[year]-[month]-[day] [hour]:[minute]:[second]")?;
     Ok(ctime.format(&format)?)
 }
 
 fn bat(path: &str) -> Result<String, Box<dyn Error>> {
+    let output = bat_command().arg(path).output();
+    Ok(String::from_utf8(output?.stdout)?)
+}
+
+/// Highlight inline command `text` as shell script.
+fn bat_text(text: &str) -> Result<String, Box<dyn Error>> {
+    let mut child = bat_command()
+        .arg("--language=sh")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(text.as_bytes())?;
+    let output = child.wait_with_output()?;
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+fn bat_command() -> Command {
     let mut cmd = Command::new("bat");
-    let cmd_mut_ref = cmd
-        .arg("--color=always")
+    cmd.arg("--color=always")
         .arg("--style=grid")
         .arg("--wrap=never");
 
     if let Ok(width) = std::env::var("FZF_PREVIEW_COLUMNS") {
         let mut width: usize = width.parse().unwrap();
         width -= 2;
-        cmd_mut_ref.arg("--terminal-width").arg(width.to_string());
+        cmd.arg("--terminal-width").arg(width.to_string());
     }
 
-    let output = cmd_mut_ref.arg(path).output();
-    Ok(String::from_utf8(output?.stdout)?)
+    cmd
 }
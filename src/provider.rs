@@ -0,0 +1,400 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::discover;
+use crate::logging::*;
+use crate::model::Action;
+
+/// Icon of actions synthesized from task runners.
+const ICON: &str = "⚙";
+
+/// A task defined by a task runner file.
+#[derive(Debug, PartialEq)]
+pub struct Task {
+    pub name: String,
+    /// Shell command running the task
+    pub command: String,
+    pub description: Option<String>,
+}
+
+/// Discovery provider synthesizing actions from tasks of an existing task runner.
+pub trait Provider {
+    /// Short name, used as prefix of action names, e.g. `make` for `make:build`
+    fn name(&self) -> &'static str;
+
+    /// Names of the task file, the first existing one is used
+    fn files(&self) -> &'static [&'static str];
+
+    /// Parse tasks from content of the task file.
+    fn tasks(&self, text: &str) -> Vec<Task>;
+}
+
+pub struct Make;
+pub struct Just;
+pub struct Npm;
+pub struct Cargo;
+
+const PROVIDERS: &[&dyn Provider] = &[&Make, &Just, &Npm, &Cargo];
+
+/// Actions of all providers, each from the nearest task file between `path` and its git root.
+pub fn actions(path: &Path) -> Vec<Action> {
+    let root = discover::git_root(path);
+    let dirs: Vec<_> = path
+        .ancestors()
+        .take_while(|dir| {
+            root.as_deref()
+                .map_or(dir == &path, |root| dir.starts_with(root))
+        })
+        .collect();
+
+    PROVIDERS
+        .iter()
+        .filter_map(|provider| {
+            let (dir, file) = dirs
+                .iter()
+                .find_map(|dir| Some((dir, task_file(*provider, dir)?)))?;
+            Some(provider_actions(*provider, dir, &file))
+        })
+        .flatten()
+        .collect()
+}
+
+/// Find the synthesized action of virtual `path`, as given by [`actions`].
+pub fn find(path: &Path) -> Option<Action> {
+    let (provider, _) = path.file_name()?.to_str()?.split_once(':')?;
+    let provider = PROVIDERS.iter().find(|x| x.name() == provider)?;
+    let dir = path.parent()?.parent()?;
+
+    let file = task_file(*provider, dir)?;
+    provider_actions(*provider, dir, &file)
+        .into_iter()
+        .find(|action| action.path == path)
+}
+
+fn task_file(provider: &dyn Provider, dir: &Path) -> Option<PathBuf> {
+    provider
+        .files()
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|file| file.is_file())
+}
+
+/// Synthesize actions from task file `file` found in project directory `dir`.
+fn provider_actions(provider: &dyn Provider, dir: &Path, file: &Path) -> Vec<Action> {
+    let text = match fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(error) => {
+            error!(
+                "failed to read file\n  path: {:#?}\n  error: {:#?}",
+                file, error
+            );
+            return Vec::new();
+        }
+    };
+
+    provider
+        .tasks(&text)
+        .into_iter()
+        .map(|task| Action {
+            path: dir
+                .join(".ap-actions")
+                .join(format!("{}:{}", provider.name(), task.name)),
+            icon: Some(ICON.to_string()),
+            title: task.command.trim_end_matches(" --").to_string(),
+            description: task.description,
+            // forward arguments to the task
+            command: Some(format!("{} \"$@\"", task.command)),
+            cwd: Some(dir.to_string_lossy().to_string()),
+            source: Some(file.to_path_buf()),
+            provider: Some(provider.name().to_string()),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Targets of a Makefile, described by `## comment` on the preceding line or the same line.
+impl Provider for Make {
+    fn name(&self) -> &'static str {
+        "make"
+    }
+
+    fn files(&self) -> &'static [&'static str] {
+        &["GNUmakefile", "makefile", "Makefile"]
+    }
+
+    fn tasks(&self, text: &str) -> Vec<Task> {
+        let mut tasks: Vec<Task> = Vec::new();
+        let mut comment = None;
+
+        for line in text.lines() {
+            if let Some(doc) = line.strip_prefix("##") {
+                comment = Some(doc.trim().to_string());
+                continue;
+            }
+            if line.starts_with(|c: char| c.is_whitespace() || c == '#' || c == '.') {
+                comment = None;
+                continue;
+            }
+
+            let (targets, rest) = match line.split_once(':') {
+                // skip variable assignments like `A := 1`, `A ::= 1`
+                Some((targets, rest)) if !rest.starts_with('=') && !rest.starts_with(":=") => {
+                    (targets, rest)
+                }
+                _ => {
+                    comment = None;
+                    continue;
+                }
+            };
+            if targets.contains(['=', '%', '$']) {
+                comment = None;
+                continue;
+            }
+
+            let description = rest
+                .split_once("##")
+                .map(|(_, doc)| doc.trim().to_string())
+                .or_else(|| comment.take());
+
+            for target in targets.split_whitespace() {
+                if !tasks.iter().any(|x| x.name == target) {
+                    tasks.push(Task {
+                        name: target.to_string(),
+                        command: format!("make {}", target),
+                        description: description.clone(),
+                    });
+                }
+            }
+            comment = None;
+        }
+
+        tasks
+    }
+}
+
+/// Recipes of a justfile, described by `# comment` on the preceding line.
+impl Provider for Just {
+    fn name(&self) -> &'static str {
+        "just"
+    }
+
+    fn files(&self) -> &'static [&'static str] {
+        &["justfile", "Justfile", ".justfile"]
+    }
+
+    fn tasks(&self, text: &str) -> Vec<Task> {
+        let mut tasks = Vec::new();
+        let mut comment = None;
+
+        for line in text.lines() {
+            if let Some(doc) = line.strip_prefix('#') {
+                comment = Some(doc.trim().to_string());
+                continue;
+            }
+
+            let recipe = line
+                .strip_prefix('@')
+                .unwrap_or(line)
+                .split(|c: char| c.is_whitespace() || c == ':')
+                .next()
+                .unwrap_or("");
+            let is_recipe = !line.starts_with(char::is_whitespace)
+                && !recipe.is_empty()
+                && recipe.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && !recipe.starts_with('_')
+                && line.contains(':')
+                && !line.contains(":=")
+                && !["set", "alias", "export", "import", "mod"].contains(&recipe);
+
+            if is_recipe {
+                tasks.push(Task {
+                    name: recipe.to_string(),
+                    command: format!("just {}", recipe),
+                    description: comment.take(),
+                });
+            }
+            comment = None;
+        }
+
+        tasks
+    }
+}
+
+/// Scripts in `package.json`.
+impl Provider for Npm {
+    fn name(&self) -> &'static str {
+        "npm"
+    }
+
+    fn files(&self) -> &'static [&'static str] {
+        &["package.json"]
+    }
+
+    fn tasks(&self, text: &str) -> Vec<Task> {
+        let json: serde_json::Value = match serde_json::from_str(text) {
+            Ok(json) => json,
+            Err(error) => {
+                warn!("failed to parse package.json: {}", error);
+                return Vec::new();
+            }
+        };
+
+        json.get("scripts")
+            .and_then(|x| x.as_object())
+            .map(|scripts| {
+                scripts
+                    .iter()
+                    .map(|(name, script)| Task {
+                        name: name.clone(),
+                        command: format!("npm run {} --", name),
+                        description: script.as_str().map(String::from),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Aliases in `.cargo/config.toml`.
+impl Provider for Cargo {
+    fn name(&self) -> &'static str {
+        "cargo"
+    }
+
+    fn files(&self) -> &'static [&'static str] {
+        &[".cargo/config.toml", ".cargo/config"]
+    }
+
+    fn tasks(&self, text: &str) -> Vec<Task> {
+        let config: toml::Value = match toml::from_str(text) {
+            Ok(config) => config,
+            Err(error) => {
+                warn!("failed to parse cargo config: {}", error);
+                return Vec::new();
+            }
+        };
+
+        config
+            .get("alias")
+            .and_then(|x| x.as_table())
+            .map(|aliases| {
+                aliases
+                    .iter()
+                    .map(|(name, value)| {
+                        let description = match value {
+                            toml::Value::String(s) => s.clone(),
+                            toml::Value::Array(words) => words
+                                .iter()
+                                .filter_map(|x| x.as_str())
+                                .collect::<Vec<_>>()
+                                .join(" "),
+                            other => other.to_string(),
+                        };
+                        Task {
+                            name: name.clone(),
+                            command: format!("cargo {}", name),
+                            description: Some(description),
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    fn names(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(|x| x.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_make_tasks() {
+        let text = "\
+.PHONY: build test
+CC := gcc
+VERSION ::= 1
+## Build everything
+build: deps
+\t$(CC) -o app main.c
+
+test lint: build ## Check it
+\t./check
+
+%.o: %.c
+\tcc $<
+";
+        let tasks = Make.tasks(text);
+        assert_eq!(names(&tasks), vec!["build", "test", "lint"]);
+        assert_eq!(tasks[0].description.as_deref(), Some("Build everything"));
+        assert_eq!(tasks[2].description.as_deref(), Some("Check it"));
+        assert_eq!(tasks[1].command, "make test");
+    }
+
+    #[test]
+    fn test_just_tasks() {
+        let text = "\
+set shell := [\"bash\", \"-c\"]
+version := \"1\"
+
+# Build it
+build target='debug':
+    cargo build
+
+@test: build
+    cargo test
+
+_hidden:
+    echo
+";
+        let tasks = Just.tasks(text);
+        assert_eq!(names(&tasks), vec!["build", "test"]);
+        assert_eq!(tasks[0].description.as_deref(), Some("Build it"));
+    }
+
+    #[test]
+    fn test_npm_tasks() {
+        let text = r#"{
+  "name": "app",
+  "scripts": {
+    "build": "tsc -p .",
+    "test": "jest --coverage"
+  }
+}"#;
+        let tasks = Npm.tasks(text);
+        assert_eq!(names(&tasks), vec!["build", "test"]);
+        assert_eq!(tasks[0].command, "npm run build --");
+        assert_eq!(tasks[1].description.as_deref(), Some("jest --coverage"));
+
+        assert!(Npm.tasks(r#"{"name": "app"}"#).is_empty());
+        assert!(Npm.tasks("{").is_empty());
+    }
+
+    #[test]
+    fn test_cargo_tasks() {
+        let text = "\
+[build]
+jobs = 4
+
+[alias]
+b = \"build --release\"
+xtask = [\"run\", \"--package\", \"xtask\", \"--\"]
+";
+        let tasks = Cargo.tasks(text);
+        assert_eq!(names(&tasks), vec!["b", "xtask"]);
+        assert_eq!(tasks[0].command, "cargo b");
+        assert_eq!(tasks[0].description.as_deref(), Some("build --release"));
+        assert_eq!(
+            tasks[1].description.as_deref(),
+            Some("run --package xtask --")
+        );
+
+        assert!(Cargo.tasks("[build]\njobs = 4").is_empty());
+        assert!(Cargo.tasks("[alias").is_empty());
+    }
+}
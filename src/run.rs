@@ -1,4 +1,4 @@
-use console::Term;
+use console::{style, Term};
 
 use std::fs::{self, OpenOptions};
 use std::io::{ErrorKind, Write};
@@ -9,35 +9,281 @@ use std::str;
 
 use crate::config::{global_actions_dir, Config, Task};
 use crate::discover;
+use crate::doctor::doctor;
 use crate::executor;
 use crate::fzf::Formatter;
+use crate::history;
+use crate::jobs;
+use crate::list::list;
 use crate::logging::*;
+use crate::logs;
 use crate::model::Action;
 use crate::preview::preview;
+use crate::settings::{DiscoverySettings, Settings};
 
-pub fn run(config: Config) {
+/// Perform the task, return the exit code of ap.
+pub fn run(config: Config) -> i32 {
+    let settings = &config.settings;
     match config.task {
-        Task::Execute { only_tmux_action } => {
-            let mut actions = discover::actions();
-            if only_tmux_action {
-                actions = actions.into_iter().filter(|x| x.tmux.is_some()).collect();
-            }
-            match choose_action(&actions) {
-                Some(lines) => executor::handle(&lines, &actions),
-                _ => {
-                    info!("nothing selected")
-                }
-            }
+        Task::Execute {
+            only_tmux_action,
+            dry_run,
+        } => pick(only_tmux_action, dry_run, None, settings),
+        Task::Run {
+            name,
+            args,
+            background,
+            dry_run,
+        } => run_action(&name, args, background, dry_run, settings),
+        Task::List { format, group } => {
+            list(&format, group.as_deref(), settings);
+            0
         }
         Task::New {
             name,
             is_global: global,
-        } => create_action(&name, global),
-        Task::Preview(path) => preview(&path),
+        } => create_action(&name, global, settings),
+        Task::History {
+            project,
+            status,
+            rerun: None,
+        } => {
+            history::show(project.as_deref(), status.as_ref());
+            0
+        }
+        Task::History {
+            rerun: Some(id), ..
+        } => {
+            let record = history::load().into_iter().nth(id.wrapping_sub(1));
+            match record {
+                Some(record) => rerun(id, record, settings),
+                None => {
+                    let s = format!("no run with ID {} in history", id);
+                    eprintln!("{}", style(s).red());
+                    1
+                }
+            }
+        }
+        Task::Again => {
+            let project = history::current_project();
+            let records = history::load();
+            match history::last_in(&records, &project) {
+                Some((id, record)) => rerun(id, record.clone(), settings),
+                None => {
+                    let s = format!("no run in history for {:?}", project);
+                    eprintln!("{}", style(s).red());
+                    1
+                }
+            }
+        }
+        Task::Doctor => doctor(settings),
+        Task::Jobs => {
+            jobs::show();
+            0
+        }
+        Task::Logs {
+            target,
+            follow,
+            last,
+        } => {
+            // a job ID takes precedence over an action named like one
+            let job = target.parse().ok().filter(|&id| jobs::load(id).is_some());
+            match job {
+                Some(job) => jobs::logs(job, follow),
+                None if follow => {
+                    let s = format!("no job with ID `{}`, `--follow` is for jobs only", target);
+                    eprintln!("{}", style(s).red());
+                    1
+                }
+                None => logs::show(&target, last),
+            }
+        }
+        Task::Attach { job } => jobs::logs(job, true),
+        Task::Stop { job } => jobs::stop(job),
+        Task::Supervise(job) => jobs::supervise(job),
+        Task::ShowConfig => {
+            settings.show();
+            0
+        }
+        Task::Preview(path) => {
+            preview(&path, settings);
+            0
+        }
     }
 }
 
-fn choose_action(actions: &[Action]) -> Option<String> {
+/// Run the action named `name` directly, without the fzf picker.
+fn run_action(
+    name: &str,
+    args: Vec<String>,
+    background: bool,
+    dry_run: bool,
+    settings: &Settings,
+) -> i32 {
+    let actions = discover::actions(&settings.discovery);
+    match discover::lookup(&actions, name).as_slice() {
+        [] => {
+            let s = format!("no action named `{}`", name);
+            eprintln!("{}", style(s).red());
+            1
+        }
+        [action] => {
+            let invocation = executor::Invocation {
+                args,
+                background,
+                dry_run,
+                ..Default::default()
+            };
+            executor::run(action, &invocation, settings).code()
+        }
+        candidates => {
+            let s = format!("action name `{}` is ambiguous, candidates:", name);
+            eprintln!("{}", style(s).red());
+            for action in candidates {
+                eprintln!(
+                    "  {:<20} {:<30} {:?}",
+                    action.name(),
+                    action.title,
+                    action.path
+                );
+            }
+            1
+        }
+    }
+}
+
+/// Run the action of history `record` again, with its original arguments and working directory.
+fn rerun(id: usize, record: history::Record, settings: &Settings) -> i32 {
+    let tip = format!("  Re-run #{} `{}` at {:?}", id, record.title, record.cwd);
+    println!("{}", style(tip).green());
+
+    if let Err(error) = std::env::set_current_dir(&record.cwd) {
+        let s = format!("failed to enter {:?}: {}", record.cwd, error);
+        eprintln!("{}", style(s).red());
+        return 1;
+    }
+
+    // discovery fills in what loading by path can not, e.g. the label of a global action, and
+    // finds actions synthesized from task runners
+    let discovery = discover::scan(&DiscoverySettings {
+        show_shadowed: true,
+        ..settings.discovery.clone()
+    });
+    let action = discovery
+        .actions
+        .into_iter()
+        .find(|x| x.path == record.path)
+        .or_else(|| Action::load_from(&record.path, &settings.discovery.action_dirs));
+    let action = match action {
+        Some(action) => action,
+        None => {
+            let s = format!("action {:?} no longer exists", record.path);
+            eprintln!("{}", style(s).red());
+            return 1;
+        }
+    };
+
+    let invocation = executor::Invocation {
+        args: record.args,
+        ..Default::default()
+    };
+    executor::run(&action, &invocation, settings).code()
+}
+
+/// Result of a round of the fzf picker.
+struct Selection {
+    query: String,
+    /// The key pressed to end fzf, empty for `enter`
+    key: String,
+    index: usize,
+}
+
+/// Picker session: run the chosen action, or edit it and pick again until the user runs an
+/// action or cancels.
+///
+/// Each round re-discovers actions, keeps the query and puts the cursor on the `focus` action
+/// unless the query filters the list. With `dry_run`, the chosen action is printed instead of run.
+fn pick(
+    only_tmux_action: bool,
+    dry_run: bool,
+    mut focus: Option<PathBuf>,
+    settings: &Settings,
+) -> i32 {
+    let mut query = String::new();
+
+    loop {
+        let mut actions = discover::actions(&settings.discovery);
+        if only_tmux_action {
+            actions.retain(|x| x.tmux.is_some());
+        }
+        history::sort(&mut actions, settings.picker.sort);
+
+        // fzf positions the cursor in the filtered list, which only matches `actions` unfiltered
+        let position = focus
+            .as_ref()
+            .filter(|_| query.is_empty())
+            .and_then(|path| actions.iter().position(|x| &x.path == path));
+        let selection = match choose_action(&actions, &query, position, settings) {
+            Some(selection) => selection,
+            None => {
+                info!("nothing selected");
+                return 0;
+            }
+        };
+
+        let action = match actions.get(selection.index) {
+            Some(action) => action,
+            None => {
+                warn!("invalid selected index: {}", selection.index);
+                return 1;
+            }
+        };
+
+        if !action.is_inline() && !action.path.exists() {
+            println!("invalid action path: {:?}, quit", action.path);
+            return 1;
+        }
+
+        debug!("pressed key: {:#?}", selection.key);
+        debug!("select path: {:#?}", action);
+
+        match selection.key.as_str() {
+            "" => {
+                let invocation = executor::Invocation {
+                    interactive: true,
+                    dry_run,
+                    ..Default::default()
+                };
+                return executor::run(action, &invocation, settings).code();
+            }
+            key if key == settings.picker.background_key => {
+                let invocation = executor::Invocation {
+                    interactive: true,
+                    background: true,
+                    dry_run,
+                    ..Default::default()
+                };
+                return executor::run(action, &invocation, settings).code();
+            }
+            key if key == settings.picker.edit_key => {
+                executor::edit_action(action, &settings.editor);
+                query = selection.query;
+                focus = Some(action.path.clone());
+            }
+            key => {
+                error!("unhandled result key: {:?}", key);
+                return 1;
+            }
+        }
+    }
+}
+
+fn choose_action(
+    actions: &[Action],
+    query: &str,
+    position: Option<usize>,
+    settings: &Settings,
+) -> Option<Selection> {
     let fzf = Formatter::new(actions);
     let feed = fzf.feed().join("\n");
 
@@ -45,18 +291,27 @@ fn choose_action(actions: &[Action]) -> Option<String> {
     cmd.env("FZF_DEFAULT_OPTS", "");
 
     // search
-    cmd.arg("--with-nth=3..").arg("--no-sort");
+    cmd.arg("--with-nth=3..")
+        .arg("--no-sort")
+        .arg("--print-query")
+        .arg(format!("--query={}", query));
+
+    if let Some(position) = position {
+        // `pos` is 1-based
+        cmd.arg("--bind").arg(format!("load:pos({})", position + 1));
+    }
 
     // appearance
+    let picker = &settings.picker;
     cmd.arg("--layout=reverse")
-        .arg("--height=60%")
-        .arg("--min-height=30")
+        .arg(format!("--height={}", picker.height))
+        .arg(format!("--min-height={}", picker.min_height))
         .arg("--ansi")
         .arg("--margin=1")
         .arg("--padding=1")
         .arg("--inline-info")
         .arg("--header")
-        .arg("Ctrl-e: edit") // sepratate line
+        .arg(picker.header()) // sepratate line
         .arg("--prompt=▶ ")
         .arg("--pointer=▶")
         .arg("--color=bg:-1,bg+:-1"); // transparent background
@@ -66,26 +321,21 @@ fn choose_action(actions: &[Action]) -> Option<String> {
         .arg("ap preview {2}")
         .arg("--preview-window");
 
-    if let Some((_, w)) = Term::stdout().size_checked() {
-        // 💀 magic number */
-        if w < 170 {
-            cmd.arg("down,70%,nowrap");
-        } else {
-            cmd.arg("right,60%,nowrap");
-        }
-    }
+    let preview = &settings.preview;
+    match Term::stdout().size_checked() {
+        Some((_, w)) if w >= preview.wide_threshold => cmd.arg(&preview.wide_window),
+        _ => cmd.arg(&preview.narrow_window),
+    };
 
     // key bindings
-    cmd.arg("--bind")
-        .arg("ctrl-f:page-down")
-        .arg("--bind")
-        .arg("ctrl-b:page-up")
-        .arg("--bind")
-        .arg("ctrl-alt-f:preview-page-down")
-        .arg("--bind")
-        .arg("ctrl-alt-b:preview-page-up");
+    for binding in &picker.bindings {
+        cmd.arg("--bind").arg(binding);
+    }
 
-    cmd.arg("--expect=ctrl-e");
+    cmd.arg(format!(
+        "--expect={},{}",
+        picker.edit_key, picker.background_key
+    ));
 
     let mut child = cmd
         // pipe
@@ -107,17 +357,17 @@ fn choose_action(actions: &[Action]) -> Option<String> {
         .expect("failed to wait `fzf` to exit");
     let output = str::from_utf8(output.stdout.as_slice()).unwrap();
 
-    // would get 2 lines if fzf not cancelled by user:
-    //   1 - the key pressed to end fzf, empty for `enter`
-    //   2 - the path of the chosen action
-    let lines = output.split('\t').take(1).collect::<String>();
-    debug!("chosen: {:?}", lines);
+    // would get 3 lines if fzf not cancelled by user:
+    //   1 - the query
+    //   2 - the key pressed to end fzf, empty for `enter`
+    //   3 - the line of the chosen action
+    debug!("chosen: {:?}", output);
+    let mut lines = output.lines();
+    let query = lines.next()?.to_string();
+    let key = lines.next()?.trim().to_string();
+    let index = lines.next()?.split('\t').next()?.parse().ok()?;
 
-    if lines.is_empty() {
-        None
-    } else {
-        Some(lines)
-    }
+    Some(Selection { query, key, index })
 }
 
 const SCRIPT_TEMPLATE: &str = "\
@@ -133,9 +383,27 @@ const META_TEMPLATE: &str = "\
 title = \"Hello world\"
 
 # description = \"\"
+
+# actions to run before this one, by name
+# depends_on = [\"build\", \"test\"]
+
+# working directory: \"project\" | \"invocation\" | \"git-root\" | path
+# cwd = \"project\"
+
+# parameters, passed to the script as positional arguments in order
+# [[params]]
+# name = \"profile\"
+# type = \"string\" # string | int | float | bool
+# default = \"debug\" # parameter without default is required
+# choices = [\"debug\", \"release\"]
+
+# environment variables, applied after those loaded from `env_file`
+# env_file = \".env\"
+# [env]
+# RUST_LOG = \"debug\"
 ";
 
-fn create_action(name: &str, global: bool) {
+fn create_action(name: &str, global: bool, settings: &Settings) -> i32 {
     // determine path
     let dir = if global {
         global_actions_dir()
@@ -150,7 +418,7 @@ fn create_action(name: &str, global: bool) {
                 "failed to create directory:\npath  {:?}\n  error: {:#?}",
                 dir, error
             );
-            return;
+            return 1;
         }
     }
 
@@ -159,6 +427,7 @@ fn create_action(name: &str, global: bool) {
     if !path.exists() {
         OpenOptions::new()
             .create(true)
+            .truncate(true)
             .write(true)
             .mode(0o731)
             .open(&path)
@@ -172,6 +441,7 @@ fn create_action(name: &str, global: bool) {
     if !meta_path.exists() {
         OpenOptions::new()
             .create(true)
+            .truncate(true)
             .write(true)
             .open(&meta_path)
             .unwrap()
@@ -179,8 +449,9 @@ fn create_action(name: &str, global: bool) {
             .unwrap();
     }
 
-    // open in editor
-    crate::executor::edit(&path);
+    // open in editor, then pick with the new action focused
+    crate::executor::edit(&path, &settings.editor);
+    pick(false, false, Some(path), settings)
 }
 
 fn choose_local_action_dir() -> PathBuf {
@@ -0,0 +1,482 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use console::{pad_str, style, Alignment};
+use serde::{Deserialize, Serialize};
+use toml::value::{Table, Value};
+
+use crate::config;
+use crate::logging::*;
+
+/// Where the effective value of a setting comes from, in ascending priority.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Source::Default => "default",
+            Source::File => "config file",
+            Source::Env => "environment",
+            Source::Cli => "command line",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Order of actions in the picker.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    /// Order of discovery: current directory, ancestors, then global
+    Discovery,
+    /// Most frequently and recently run in current project first
+    Frecency,
+    /// By title
+    Alpha,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PickerSettings {
+    /// `--height` of fzf
+    pub height: String,
+
+    /// `--min-height` of fzf
+    pub min_height: u32,
+
+    /// Header line shown above the list, empty for hints of `edit_key` and `background_key`
+    pub header: String,
+
+    /// Key to edit the selected action instead of running it
+    pub edit_key: String,
+
+    /// Key to run the selected action as a background job
+    pub background_key: String,
+
+    /// Extra fzf `--bind` key bindings
+    pub bindings: Vec<String>,
+
+    /// Order of actions: "discovery", "frecency" or "alpha"
+    pub sort: Sort,
+}
+
+impl PickerSettings {
+    /// The configured header, or hints of the configured keys, e.g. "Ctrl-e: edit".
+    pub fn header(&self) -> String {
+        if !self.header.is_empty() {
+            return self.header.clone();
+        }
+
+        let key = |key: &str| {
+            let mut chars = key.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        };
+        format!(
+            "{}: edit, {}: run in background",
+            key(&self.edit_key),
+            key(&self.background_key)
+        )
+    }
+}
+
+impl Default for PickerSettings {
+    fn default() -> Self {
+        PickerSettings {
+            height: "60%".to_string(),
+            min_height: 30,
+            header: String::new(),
+            edit_key: "ctrl-e".to_string(),
+            background_key: "alt-enter".to_string(),
+            bindings: vec![
+                "ctrl-f:page-down".to_string(),
+                "ctrl-b:page-up".to_string(),
+                "ctrl-alt-f:preview-page-down".to_string(),
+                "ctrl-alt-b:preview-page-up".to_string(),
+            ],
+            sort: Sort::Frecency,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreviewSettings {
+    /// Terminal width from which the preview window is put on the right side instead of below
+    pub wide_threshold: u16,
+
+    /// fzf `--preview-window` for terminals at least `wide_threshold` columns wide
+    pub wide_window: String,
+
+    /// fzf `--preview-window` for narrower terminals
+    pub narrow_window: String,
+}
+
+impl Default for PreviewSettings {
+    fn default() -> Self {
+        PreviewSettings {
+            wide_threshold: 170,
+            wide_window: "right,60%,nowrap".to_string(),
+            narrow_window: "down,70%,nowrap".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscoverySettings {
+    /// Stop looking for actions in ancestors at the first git root
+    pub stop_at_git_root: bool,
+
+    /// Stop looking for actions in ancestors at the home directory
+    pub stop_at_home: bool,
+
+    /// Stop looking for actions in ancestors at the first directory containing this file, empty
+    /// to disable
+    pub root_marker: String,
+
+    /// Max number of directories to look for actions in, starting from current directory, 0 for
+    /// no limit
+    pub max_depth: u32,
+
+    /// Show actions shadowed by ones of the same name in a closer scope, dimmed in the picker
+    pub show_shadowed: bool,
+
+    /// Extra global action directories, each as `{label}={path}` or `{path}`
+    pub action_dirs: Vec<String>,
+}
+
+impl Default for DiscoverySettings {
+    fn default() -> Self {
+        DiscoverySettings {
+            stop_at_git_root: true,
+            stop_at_home: true,
+            root_marker: ".ap-root".to_string(),
+            max_depth: 0,
+            show_shadowed: false,
+            action_dirs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    /// Save output of each foreground run to a log file, see `ap logs`
+    pub enabled: bool,
+
+    /// Number of most recent logs to keep for each action, 0 for no limit
+    pub keep_runs: u32,
+
+    /// Remove logs older than this many days, 0 for no limit
+    pub keep_days: u32,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            enabled: true,
+            keep_runs: 20,
+            keep_days: 30,
+        }
+    }
+}
+
+/// Settings of ap, merged from defaults, the config file, `AP_*` environment variables and
+/// command line flags.
+///
+/// Each setting can be overridden by an environment variable named after its dotted key, e.g.
+/// `picker.height` by `AP_PICKER_HEIGHT`. Array values are separated by commas.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Editor command template used to edit actions, `{script}` and `{meta}` are replaced by the
+    /// paths of the action script and its toml file. Empty to use `$VISUAL` or `$EDITOR`
+    pub editor: String,
+
+    pub picker: PickerSettings,
+
+    pub preview: PreviewSettings,
+
+    pub discovery: DiscoverySettings,
+
+    pub logs: LogSettings,
+
+    /// Source of each setting, keyed by dotted key
+    #[serde(skip)]
+    pub sources: BTreeMap<String, Source>,
+}
+
+/// Path of the config file: `<config_dir>/config.toml`
+pub fn path() -> PathBuf {
+    config::dir().join("config.toml")
+}
+
+impl Settings {
+    /// Load settings, `overrides` are `(dotted key, value)` pairs from command line flags.
+    pub fn load(overrides: Vec<(&str, Value)>) -> Settings {
+        let mut merged = Value::try_from(Settings::default()).unwrap();
+        let table = merged.as_table_mut().unwrap();
+        let mut sources = BTreeMap::new();
+
+        // config file
+        if let Some(file) = read_file(&path()) {
+            overlay(table, file, "", Source::File, &mut sources);
+        }
+
+        // environment variables
+        for (key, value) in env_overrides(table) {
+            set(table, &key, value, Source::Env, &mut sources);
+        }
+
+        // command line flags
+        for (key, value) in overrides {
+            set(table, key, value, Source::Cli, &mut sources);
+        }
+
+        let mut settings: Settings = merged.try_into().unwrap_or_else(|error| {
+            error!("failed to build settings: {:#?}", error);
+            Settings::default()
+        });
+        settings.sources = sources;
+        settings
+    }
+
+    pub fn source(&self, key: &str) -> Source {
+        self.sources.get(key).copied().unwrap_or(Source::Default)
+    }
+
+    /// Print the effective settings and where each value comes from.
+    pub fn show(&self) {
+        let path = path();
+        let exists = if path.exists() { "" } else { " (not found)" };
+        let s = format!("# config file: {}{}", path.display(), exists);
+        println!("{}", style(s).dim());
+
+        let value = Value::try_from(self).unwrap();
+        for (key, value) in leaves(value.as_table().unwrap(), "") {
+            let source = self.source(&key);
+            let key = pad_str(&key, 28, Alignment::Left, None).to_string();
+            let value = format!("{} = {}", key, value);
+            let value = pad_str(&value, 64, Alignment::Left, None).to_string();
+            let source = format!("# {}", source);
+            println!("{}{}", value, style(source).dim());
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Option<Table> {
+    let text = fs::read_to_string(path).ok()?;
+    match toml::from_str::<Table>(&text) {
+        Ok(table) => Some(table),
+        Err(error) => {
+            error!(
+                "failed to parse config file\n  path: {:#?}\n  error: {:#?}",
+                path, error
+            );
+            None
+        }
+    }
+}
+
+/// Flatten nested tables into `(dotted key, value)` pairs.
+fn leaves(table: &Table, prefix: &str) -> Vec<(String, Value)> {
+    table
+        .iter()
+        .flat_map(|(key, value)| {
+            let key = join(prefix, key);
+            match value {
+                Value::Table(table) => leaves(table, &key),
+                value => vec![(key, value.clone())],
+            }
+        })
+        .collect()
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+/// Merge `top` into `base`, only known keys with valid values are accepted.
+fn overlay(
+    base: &mut Table,
+    top: Table,
+    prefix: &str,
+    source: Source,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    for (key, value) in top {
+        let dotted = join(prefix, &key);
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(top)) => {
+                overlay(base, top, &dotted, source, sources)
+            }
+            (Some(old), value) if old.same_type(&value) => match check(&dotted, &value) {
+                Ok(()) => {
+                    *old = value;
+                    sources.insert(dotted, source);
+                }
+                Err(error) => warn!("ignore setting `{}`: {}", dotted, error),
+            },
+            (Some(old), value) => warn!(
+                "ignore setting `{}`: expecting {}, found {}",
+                dotted,
+                old.type_str(),
+                value.type_str()
+            ),
+            (None, _) => warn!("ignore unknown setting `{}`", dotted),
+        }
+    }
+}
+
+/// Check `value` of setting `key` beyond its type, e.g. that a string names a variant of an enum,
+/// by deserializing it among the defaults of other settings.
+fn check(key: &str, value: &Value) -> Result<(), String> {
+    let mut settings = Value::try_from(Settings::default()).unwrap();
+    let mut table = settings.as_table_mut().unwrap();
+    let mut parts = key.split('.').peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            table.insert(part.to_string(), value.clone());
+            break;
+        }
+        table = match table.get_mut(part).and_then(|x| x.as_table_mut()) {
+            Some(table) => table,
+            None => return Ok(()),
+        };
+    }
+
+    settings
+        .try_into::<Settings>()
+        .map(|_| ())
+        .map_err(|error| error.to_string())
+}
+
+fn set(
+    table: &mut Table,
+    key: &str,
+    value: Value,
+    source: Source,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    let mut top = Table::new();
+    let mut parts = key.rsplit('.');
+    top.insert(parts.next().unwrap().to_string(), value);
+    for part in parts {
+        let mut parent = Table::new();
+        parent.insert(part.to_string(), Value::Table(top));
+        top = parent;
+    }
+
+    overlay(table, top, "", source, sources);
+}
+
+/// Collect `AP_*` environment variables of known settings, parsed after the type of the default
+/// values.
+fn env_overrides(defaults: &Table) -> Vec<(String, Value)> {
+    leaves(defaults, "")
+        .into_iter()
+        .filter_map(|(key, default)| {
+            let name = format!("AP_{}", key.replace('.', "_").to_uppercase());
+            let text = std::env::var(&name).ok()?;
+
+            let value = match default {
+                Value::String(_) => Some(Value::String(text.clone())),
+                Value::Integer(_) => text.parse().ok().map(Value::Integer),
+                Value::Float(_) => text.parse().ok().map(Value::Float),
+                Value::Boolean(_) => text.parse().ok().map(Value::Boolean),
+                Value::Array(_) => Some(Value::Array(
+                    text.split(',')
+                        .map(|x| Value::String(x.trim().to_string()))
+                        .collect(),
+                )),
+                _ => None,
+            };
+
+            if value.is_none() {
+                warn!("ignore invalid value of `{}`: {:?}", name, text);
+            }
+            value.map(|value| (key, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    #[test]
+    fn test_overlay() {
+        let mut base = Value::try_from(Settings::default()).unwrap();
+        let base = base.as_table_mut().unwrap();
+        let top: Table = toml::from_str(
+            r#"
+            editor = "hx"
+            unknown = 1
+
+            [picker]
+            height = 40
+            min_height = 20
+            sort = "foo"
+            "#,
+        )
+        .unwrap();
+
+        let mut sources = BTreeMap::new();
+        overlay(base, top, "", Source::File, &mut sources);
+        set(
+            base,
+            "picker.header",
+            Value::String("hi".to_string()),
+            Source::Cli,
+            &mut sources,
+        );
+
+        let settings: Settings = Value::Table(base.clone()).try_into().unwrap();
+        assert_eq!(settings.editor, "hx");
+        assert_eq!(settings.picker.height, "60%");
+        assert_eq!(settings.picker.min_height, 20);
+        assert_eq!(settings.picker.header, "hi");
+        assert_eq!(settings.picker.sort, PickerSettings::default().sort);
+
+        assert_eq!(sources.get("editor"), Some(&Source::File));
+        assert_eq!(sources.get("picker.height"), None);
+        assert_eq!(sources.get("picker.sort"), None);
+        assert_eq!(sources.get("picker.header"), Some(&Source::Cli));
+    }
+
+    #[test]
+    fn test_picker_header() {
+        let mut picker = PickerSettings::default();
+        assert_eq!(
+            picker.header(),
+            "Ctrl-e: edit, Alt-enter: run in background"
+        );
+
+        picker.edit_key = "ctrl-o".to_string();
+        assert_eq!(
+            picker.header(),
+            "Ctrl-o: edit, Alt-enter: run in background"
+        );
+
+        picker.header = "hi".to_string();
+        assert_eq!(picker.header(), "hi");
+    }
+}
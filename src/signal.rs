@@ -0,0 +1,153 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use libc::{c_int, c_void, siginfo_t};
+
+/// Pid of the running child to forward signals to, 0 if none.
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Whether ap was asked to stop by SIGINT or SIGTERM sent with `kill(2)`.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+const SIGNALS: [c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+extern "C" fn forward(signal: c_int, info: *mut siginfo_t, _: *mut c_void) {
+    let pid = CHILD.load(Ordering::SeqCst);
+
+    // signals from the terminal (e.g. ctrl-c) already reach the child, which shares the foreground
+    // process group with ap, only forward those sent to ap explicitly by `kill(2)`
+    let explicit = info.is_null() || unsafe { (*info).si_code } == libc::SI_USER;
+
+    if explicit {
+        STOPPED.store(true, Ordering::SeqCst);
+    }
+    if pid > 0 && explicit {
+        terminate(pid, signal);
+    }
+}
+
+extern "C" fn note(_: c_int) {
+    STOPPED.store(true, Ordering::SeqCst);
+}
+
+/// Survive SIGINT and SIGTERM, e.g. those sent to the whole process group, to outlive the child
+/// process, noting them for [`stop_requested`]. Unlike `SIG_IGN`, a handler is reset to default
+/// for children on exec.
+pub fn catch() {
+    for signal in SIGNALS {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = note as *const () as usize;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Whether ap received SIGINT or SIGTERM while catching them, or one was forwarded to the child,
+/// i.e. the user asked to stop, e.g. by `ap stop`.
+pub fn stop_requested() -> bool {
+    STOPPED.load(Ordering::SeqCst)
+}
+
+/// Forward SIGINT and SIGTERM received by ap to the child process `pid`, or the process group it
+/// leads, as long as the guard lives, so that ap outlives the child and can report its exit
+/// status.
+pub struct Forwarding;
+
+impl Forwarding {
+    pub fn new(pid: u32) -> Forwarding {
+        CHILD.store(pid as i32, Ordering::SeqCst);
+
+        for signal in SIGNALS {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = forward as *const () as usize;
+                action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+            }
+        }
+
+        Forwarding
+    }
+}
+
+impl Drop for Forwarding {
+    fn drop(&mut self) {
+        CHILD.store(0, Ordering::SeqCst);
+
+        for signal in SIGNALS {
+            unsafe {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+        }
+    }
+}
+
+/// Terminate the child process `pid` once `timeout` elapses as long as the guard lives, with
+/// SIGTERM first, then SIGKILL if it is still alive after `grace`.
+///
+/// The signals go to the process group led by the child if there is one, e.g. a child in its own
+/// session, so that its descendants are terminated as well.
+pub struct Watchdog {
+    cancel: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    fired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    pub fn new(pid: u32, timeout: Duration, grace: Duration) -> Watchdog {
+        let (cancel, cancelled) = mpsc::channel::<()>();
+        let fired = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let fired = fired.clone();
+            thread::spawn(move || {
+                if cancelled.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout) {
+                    return;
+                }
+                fired.store(true, Ordering::SeqCst);
+                terminate(pid as i32, libc::SIGTERM);
+
+                if cancelled.recv_timeout(grace) == Err(RecvTimeoutError::Timeout) {
+                    terminate(pid as i32, libc::SIGKILL);
+                }
+            })
+        };
+
+        Watchdog {
+            cancel: Some(cancel),
+            thread: Some(thread),
+            fired,
+        }
+    }
+
+    /// Whether the timeout elapsed and the child was signaled.
+    pub fn fired(&self) -> bool {
+        self.fired.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        // disconnect to wake up the thread
+        self.cancel.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Send `signal` to the process group led by `pid`, or to `pid` alone if there is none.
+pub fn terminate(pid: i32, signal: c_int) {
+    unsafe {
+        if libc::kill(-pid, signal) != 0 {
+            libc::kill(pid, signal);
+        }
+    }
+}